@@ -0,0 +1,881 @@
+//! Client side of the protocol. The `ClientKey` lives only inside `Client`, so the server
+//! module, which works with the `ServerKey` alone, has no way to reach it.
+
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{
+    self, BinaryOperator, Distinct, Expr, GroupByExpr, SelectItem, SetExpr, Statement, TableFactor,
+    UnaryOperator,
+};
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::Parser;
+use tfhe::{
+    generate_keys, prelude::*, ClientKey, CompressedServerKey, Config, FheBool, FheInt16, FheInt32,
+    FheInt64, FheInt8, FheUint16, FheUint32, FheUint64, FheUint8, ServerKey,
+};
+
+use crate::keys::{self, KeyError, KeyKind};
+use crate::parameters::ParameterProfile;
+use crate::query::{
+    Arithmetic, ArithmeticOp, ColumnRef, ComparisonOp, ConcatPart, EncryptedCell, EncryptedInteger,
+    EncryptedLiteral, EncryptedQuery, EncryptedResult, Operand, Predicate, Projection, QueryError,
+    TableSelector, DEFAULT_STRING_LENGTH, MAX_DEPTH,
+};
+use crate::results::ResultSet;
+use crate::schema::{DataType, IntegerType, Row, Schema, TableSchema, Value};
+
+pub(crate) struct Client {
+    client_key: ClientKey,
+    // Number of bytes string literals are padded to. Longer literals are rejected.
+    string_length: usize,
+}
+
+impl Client {
+    /// Generates a fresh keypair. The server key is handed out, the client key stays here.
+    pub fn new(config: Config) -> (Client, ServerKey) {
+        let (client_key, server_key) = generate_keys(config);
+        (Client::from_key(client_key), server_key)
+    }
+
+    // Server key in its compressed form, which is much smaller to store or send.
+    pub fn compressed_server_key(&self) -> CompressedServerKey {
+        CompressedServerKey::new(&self.client_key)
+    }
+
+    /// Writes the client key to `dir`.
+    pub fn save(&self, dir: &Path, profile: ParameterProfile) -> Result<(), KeyError> {
+        fs::create_dir_all(dir).map_err(|err| KeyError::Io(dir.to_path_buf(), err))?;
+        keys::write_key(
+            &dir.join(keys::CLIENT_KEY_FILE),
+            KeyKind::Client,
+            profile,
+            &self.client_key,
+        )
+    }
+
+    /// Loads the client key saved in `dir`, checking it was generated with `expected` if given.
+    pub fn load(
+        dir: &Path,
+        expected: Option<ParameterProfile>,
+    ) -> Result<(Client, ParameterProfile), KeyError> {
+        let (client_key, profile) =
+            keys::read_key(&dir.join(keys::CLIENT_KEY_FILE), KeyKind::Client, expected)?;
+        Ok((Client::from_key(client_key), profile))
+    }
+
+    fn from_key(client_key: ClientKey) -> Client {
+        Client {
+            client_key,
+            string_length: DEFAULT_STRING_LENGTH,
+        }
+    }
+
+    /// Sets the number of bytes string literals are padded to. Comparing strings costs more
+    /// with longer literals, but longer literals are accepted.
+    pub fn set_string_length(&mut self, string_length: usize) {
+        self.string_length = string_length;
+    }
+
+    // Encrypt a SQL query by parsing it and compiling the AST into the encrypted IR.
+    pub fn encrypt_query(
+        &self,
+        query: &str,
+        schema: &Schema,
+        access: TableAccess,
+    ) -> Result<(EncryptedQuery, ResultLayout), QueryError> {
+        let dialect = GenericDialect {};
+        let ast = Parser::parse_sql(&dialect, query)?;
+
+        let (query, select) = match ast.as_slice() {
+            [Statement::Query(query)] => match query.body.as_ref() {
+                SetExpr::Select(select) => (query, select),
+                other => return Err(QueryError::Unsupported(format!("query body `{}`", other))),
+            },
+            [other] => return Err(QueryError::Unsupported(format!("statement `{}`", other))),
+            [] => return Err(QueryError::Unsupported("empty query".to_string())),
+            _ => {
+                return Err(QueryError::Unsupported(
+                    "more than one statement".to_string(),
+                ))
+            }
+        };
+        // Clauses the server cannot run are rejected rather than ignored, which would return
+        // other rows than asked for.
+        if let Some(clause) = unsupported_clause(query, select) {
+            return Err(QueryError::Unsupported(clause.to_string()));
+        }
+
+        // Parsing FROM
+        let from = match select.from.as_slice() {
+            [table] if table.joins.is_empty() => match &table.relation {
+                TableFactor::Table { name, .. } => match name.0.last() {
+                    Some(ident) => ident.value.clone(),
+                    None => return Err(QueryError::Unsupported("empty table name".to_string())),
+                },
+                other => return Err(QueryError::Unsupported(format!("FROM `{}`", other))),
+            },
+            _ => {
+                return Err(QueryError::Unsupported(
+                    "FROM must name exactly one table".to_string(),
+                ))
+            }
+        };
+        let position = schema
+            .table_position(&from)
+            .ok_or_else(|| QueryError::UnknownTable(from.clone()))?;
+        let table = match access {
+            TableAccess::Oblivious => TableSelector::Encrypted(FheUint8::encrypt(
+                to_position(position)?,
+                &self.client_key,
+            )),
+            TableAccess::Clear => TableSelector::Clear(from.clone()),
+        };
+
+        let mut compiler = QueryCompiler {
+            client_key: &self.client_key,
+            string_length: self.string_length,
+            table: &schema.tables[position],
+            access,
+            columns: Vec::new(),
+        };
+
+        // A wildcard expands to as many cells as the widest candidate table has columns.
+        let table_columns = schema.tables[position].columns.len();
+        let wildcard_width = match access {
+            TableAccess::Oblivious => schema.max_columns(),
+            TableAccess::Clear => table_columns,
+        };
+
+        // Handling projection (SELECT): each named column becomes its encrypted position in the
+        // table, so the server can select it without learning which one was asked for.
+        let mut projection = Vec::new();
+        let mut layout = ResultLayout { cells: Vec::new() };
+        let mut cell = 0;
+        for item in &select.projection {
+            match item {
+                SelectItem::Wildcard(_) | SelectItem::UnnamedExpr(Expr::Wildcard) => {
+                    projection.push(Projection::Wildcard);
+                    layout.cells.extend(
+                        schema.tables[position]
+                            .columns
+                            .iter()
+                            .enumerate()
+                            .map(|(index, column)| (cell + index, column.data_type)),
+                    );
+                    cell += wildcard_width;
+                }
+                SelectItem::UnnamedExpr(Expr::Identifier(ident))
+                | SelectItem::ExprWithAlias {
+                    expr: Expr::Identifier(ident),
+                    ..
+                } => {
+                    let column = compiler
+                        .table
+                        .column(&ident.value)
+                        .ok_or_else(|| QueryError::UnknownColumn(ident.value.clone()))?;
+                    layout.cells.push((cell, column.data_type));
+                    projection.push(Projection::Column(compiler.column_slot(&ident.value)?));
+                    cell += 1;
+                }
+                SelectItem::UnnamedExpr(expr @ Expr::BinaryOp { .. })
+                | SelectItem::ExprWithAlias {
+                    expr: expr @ Expr::BinaryOp { .. },
+                    ..
+                } => {
+                    if compiler.is_string(expr) {
+                        let mut parts = Vec::new();
+                        compiler.concat_parts(expr, &mut parts)?;
+                        layout.cells.push((cell, DataType::String));
+                        projection.push(Projection::Concat(parts));
+                    } else {
+                        let arithmetic = compiler.compile_arithmetic(expr)?;
+                        let data_type = DataType::Integer(arithmetic.integer_type);
+                        layout.cells.push((cell, data_type));
+                        projection.push(Projection::Arithmetic(arithmetic));
+                    }
+                    cell += 1;
+                }
+                other => return Err(QueryError::Unsupported(format!("projection `{}`", other))),
+            }
+        }
+
+        let distinct = match &select.distinct {
+            None => false,
+            Some(Distinct::Distinct) => true,
+            Some(Distinct::On(_)) => {
+                return Err(QueryError::Unsupported("DISTINCT ON".to_string()))
+            }
+        };
+
+        // Handling WHERE and logical operators
+        let selection = match &select.selection {
+            Some(expr) => Some(compiler.compile_predicate(expr)?),
+            None => None,
+        };
+
+        let query = EncryptedQuery {
+            table,
+            columns: compiler.columns,
+            projection,
+            distinct,
+            selection,
+            max_rows: None,
+        };
+        if query.depth() > MAX_DEPTH {
+            return Err(QueryError::Unsupported(format!(
+                "expressions nested deeper than {}",
+                MAX_DEPTH
+            )));
+        }
+        Ok((query, layout))
+    }
+
+    pub fn decrypt_result(
+        &self,
+        encrypted_result: &EncryptedResult,
+        layout: &ResultLayout,
+    ) -> ResultSet {
+        let mut decrypted_rows = Vec::new();
+
+        // The selected rows come first, the rows after them are empty.
+        let count: u32 = encrypted_result.count.decrypt(&self.client_key);
+        let count = count as usize;
+        if count > encrypted_result.rows.len() {
+            eprintln!(
+                "{} row(s) matched, only the first {} were returned",
+                count,
+                encrypted_result.rows.len()
+            );
+        }
+
+        for row in encrypted_result.rows.iter().take(count) {
+            let values: Vec<Value> = layout
+                .cells
+                .iter()
+                .filter_map(|&(position, data_type)| {
+                    row.cells
+                        .get(position)
+                        .map(|cell| self.decrypt_cell(cell, data_type))
+                })
+                .collect();
+            decrypted_rows.push(Row { values });
+        }
+
+        ResultSet {
+            rows: decrypted_rows,
+        }
+    }
+
+    // Decrypts a cell and decodes its bytes with the type of the column it was projected from.
+    fn decrypt_cell(&self, cell: &EncryptedCell, data_type: DataType) -> Value {
+        let mut bytes: Vec<u8> = cell
+            .bytes
+            .iter()
+            .map(|byte| byte.decrypt(&self.client_key))
+            .collect();
+        if let Some(error) = &cell.error {
+            let error: bool = error.decrypt(&self.client_key);
+            if error {
+                return Value::Null;
+            }
+        }
+        match data_type {
+            DataType::Integer(integer_type) => {
+                // Little-endian at the width of the type, sign-extended for signed types.
+                let width = (integer_type.bits() / 8) as usize;
+                bytes.resize(8, 0);
+                let value = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+                let shift = 64 - 8 * width as u32;
+                if integer_type.is_signed() {
+                    Value::Integer(((value << shift) as i64) >> shift)
+                } else {
+                    Value::Integer(((value << shift) >> shift) as i64)
+                }
+            }
+            DataType::Boolean => Value::Boolean(bytes.first().copied().unwrap_or(0) != 0),
+            DataType::String => {
+                match &cell.length {
+                    // A concatenation carries its length.
+                    Some(length) => {
+                        let length: u16 = length.decrypt(&self.client_key);
+                        bytes.truncate(length as usize);
+                    }
+                    // Strip the zero padding added by the server.
+                    None => {
+                        while bytes.last() == Some(&0) {
+                            bytes.pop();
+                        }
+                    }
+                }
+                Value::String(String::from_utf8_lossy(&bytes).into_owned())
+            }
+        }
+    }
+}
+
+/// What the client needs to read back the result of a query it encrypted. It is derived from the
+/// cleartext query and never leaves the client.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ResultLayout {
+    // Positions of the result cells holding projected values, the others are padding, with the
+    // type of the column each was projected from.
+    cells: Vec<(usize, DataType)>,
+}
+
+impl ResultLayout {
+    // Types of the projected values, in the order of the result columns.
+    pub fn column_types(&self) -> Vec<DataType> {
+        self.cells.iter().map(|&(_, data_type)| data_type).collect()
+    }
+}
+
+/// Whether the client hides the queried table from the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TableAccess {
+    // The table and column positions are encrypted and the server scans every table. The
+    // shape of the query is still sent in clear, and with it the types of the columns it uses:
+    // the kind and width of each literal (`EncryptedInteger` variant, bool or string), the type
+    // of each arithmetic expression, string concatenations, and bool columns used as predicates.
+    // When the tables differ in these types, the server can rule out the tables whose columns do
+    // not match, and may learn which table and columns are queried.
+    Oblivious,
+    // The table name is sent in clear: faster, but the server learns which table is queried.
+    Clear,
+}
+
+// Compilation state for one query: the table it targets and the encrypted column positions
+// collected so far.
+struct QueryCompiler<'a> {
+    client_key: &'a ClientKey,
+    string_length: usize,
+    table: &'a TableSchema,
+    access: TableAccess,
+    columns: Vec<FheUint8>,
+}
+
+impl QueryCompiler<'_> {
+    // Encrypts the position of a column and returns its slot in `EncryptedQuery::columns`.
+    fn column_slot(&mut self, name: &str) -> Result<usize, QueryError> {
+        let position = self
+            .table
+            .column_index(name)
+            .ok_or_else(|| QueryError::UnknownColumn(name.to_string()))?;
+        self.columns
+            .push(FheUint8::encrypt(to_position(position)?, self.client_key));
+        Ok(self.columns.len() - 1)
+    }
+
+    // Flattens a concatenation such as `first || ' ' || last` into its parts, string columns and
+    // string literals. `+` concatenates strings too.
+    fn concat_parts(&mut self, expr: &Expr, parts: &mut Vec<ConcatPart>) -> Result<(), QueryError> {
+        match expr {
+            Expr::Nested(inner) => self.concat_parts(inner, parts),
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::StringConcat | BinaryOperator::Plus,
+                right,
+            } => {
+                self.concat_parts(left, parts)?;
+                self.concat_parts(right, parts)
+            }
+            Expr::Identifier(ident) => match self.expr_type(expr) {
+                Some(DataType::String) => {
+                    parts.push(ConcatPart::Column(self.column_slot(&ident.value)?));
+                    Ok(())
+                }
+                Some(data_type) => Err(QueryError::Unsupported(format!(
+                    "concatenation of the {} column `{}`",
+                    data_type, ident.value
+                ))),
+                None => Err(QueryError::UnknownColumn(ident.value.clone())),
+            },
+            Expr::Value(ast::Value::SingleQuotedString(s)) => {
+                parts.push(ConcatPart::Literal(encrypt_string(
+                    s,
+                    self.string_length,
+                    self.client_key,
+                )?));
+                Ok(())
+            }
+            other => Err(QueryError::Unsupported(format!(
+                "concatenation of `{}`",
+                other
+            ))),
+        }
+    }
+
+    // Whether an expression is a string, so that `+` concatenates rather than adds. SQLite adds
+    // strings as numbers instead, so the clear result of such a query differs.
+    fn is_string(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Nested(inner) => self.is_string(inner),
+            Expr::Value(ast::Value::SingleQuotedString(_)) => true,
+            Expr::BinaryOp {
+                op: BinaryOperator::StringConcat,
+                ..
+            } => true,
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::Plus,
+                right,
+            } => self.is_string(left) || self.is_string(right),
+            _ => self.expr_type(expr) == Some(DataType::String),
+        }
+    }
+
+    // Compiles an arithmetic expression such as `price * qty`. Its literals are encrypted with
+    // the type of the result, or a wider one when it cannot hold them, and the result wraps
+    // around at that type: `qty * 1000` is computed modulo 256 if `qty` is a uint8 column.
+    fn compile_arithmetic(&mut self, expr: &Expr) -> Result<Arithmetic, QueryError> {
+        let (left, op, right) = match expr {
+            Expr::Nested(inner) => return self.compile_arithmetic(inner),
+            Expr::BinaryOp { left, op, right } => match ArithmeticOp::from_binary_operator(op) {
+                Some(arithmetic_op) => (left, arithmetic_op, right),
+                None => return Err(QueryError::Unsupported(format!("operator `{}`", op))),
+            },
+            other => return Err(QueryError::Unsupported(format!("expression `{}`", other))),
+        };
+        for operand in [left, right] {
+            match self.expr_type(operand) {
+                Some(DataType::Integer(_)) | None => {}
+                Some(data_type) => {
+                    return Err(QueryError::Unsupported(format!(
+                        "arithmetic on the {} value `{}`",
+                        data_type, operand
+                    )))
+                }
+            }
+        }
+        let integer_type = match self.expr_type(expr) {
+            Some(DataType::Integer(integer_type)) => integer_type,
+            _ => {
+                return Err(QueryError::Unsupported(format!(
+                    "arithmetic without a column `{}`",
+                    expr
+                )))
+            }
+        };
+        let data_type = Some(DataType::Integer(integer_type));
+        Ok(Arithmetic {
+            left: self.compile_operand(left, data_type)?,
+            op,
+            right: self.compile_operand(right, data_type)?,
+            integer_type,
+        })
+    }
+
+    // Compiles the expressions typically found in a WHERE clause into a predicate tree.
+    fn compile_predicate(&mut self, expr: &Expr) -> Result<Predicate, QueryError> {
+        match expr {
+            Expr::Nested(inner) => self.compile_predicate(inner),
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            } => Ok(Predicate::And(
+                Box::new(self.compile_predicate(left)?),
+                Box::new(self.compile_predicate(right)?),
+            )),
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::Or,
+                right,
+            } => Ok(Predicate::Or(
+                Box::new(self.compile_predicate(left)?),
+                Box::new(self.compile_predicate(right)?),
+            )),
+            Expr::UnaryOp {
+                op: UnaryOperator::Not,
+                expr,
+            } => Ok(Predicate::Not(Box::new(self.compile_predicate(expr)?))),
+            Expr::Identifier(ident) => match self.expr_type(expr) {
+                Some(DataType::Boolean) => Ok(Predicate::Column(self.column_ref(&ident.value)?)),
+                Some(data_type) => Err(QueryError::Unsupported(format!(
+                    "the {} column `{}` used as a predicate",
+                    data_type, ident.value
+                ))),
+                None => Err(QueryError::UnknownColumn(ident.value.clone())),
+            },
+            Expr::BinaryOp { left, op, right } => {
+                let op = ComparisonOp::from_binary_operator(op)
+                    .ok_or_else(|| QueryError::Unsupported(format!("operator `{}`", op)))?;
+                // Two columns, or arithmetic results, are compared as integers on the server,
+                // where strings cannot be rebuilt as one.
+                if let (Some(left_type), Some(right_type)) =
+                    (self.expr_type(left), self.expr_type(right))
+                {
+                    if left_type == DataType::String || right_type == DataType::String {
+                        return Err(QueryError::Unsupported(format!(
+                            "`{}`: comparison between a string and another column",
+                            expr
+                        )));
+                    }
+                }
+                // A literal takes the type of the column it is compared with.
+                let data_type = self.expr_type(left).or_else(|| self.expr_type(right));
+                if data_type == Some(DataType::String)
+                    && !matches!(op, ComparisonOp::Eq | ComparisonOp::NotEq)
+                {
+                    return Err(QueryError::Unsupported(format!(
+                        "`{}`: strings only compare with = and <>",
+                        expr
+                    )));
+                }
+                Ok(Predicate::Compare {
+                    left: self.compile_operand(left, data_type)?,
+                    op,
+                    right: self.compile_operand(right, data_type)?,
+                })
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let data_type = self.expr_type(expr);
+                let list = list
+                    .iter()
+                    .map(|item| self.encrypt_literal(item, data_type))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Predicate::InList {
+                    expr: self.compile_operand(expr, data_type)?,
+                    list,
+                    negated: *negated,
+                })
+            }
+            Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => {
+                let data_type = self.expr_type(expr);
+                if data_type == Some(DataType::String) {
+                    return Err(QueryError::Unsupported(format!(
+                        "BETWEEN on strings `{}`",
+                        expr
+                    )));
+                }
+                // The server compares a column with two encrypted bounds only.
+                if !is_column(expr) || !is_literal(low) || !is_literal(high) {
+                    return Err(QueryError::Unsupported(
+                        "BETWEEN must compare a column with two literals".to_string(),
+                    ));
+                }
+                Ok(Predicate::Between {
+                    expr: self.compile_operand(expr, data_type)?,
+                    low: self.compile_operand(low, data_type)?,
+                    high: self.compile_operand(high, data_type)?,
+                    negated: *negated,
+                })
+            }
+            other => Err(QueryError::Unsupported(format!("predicate `{}`", other))),
+        }
+    }
+
+    // Compiles a leaf of a comparison: either a column reference or an encrypted literal typed
+    // after the column it is compared with.
+    fn compile_operand(
+        &mut self,
+        expr: &Expr,
+        data_type: Option<DataType>,
+    ) -> Result<Operand, QueryError> {
+        match expr {
+            Expr::Nested(inner) => self.compile_operand(inner, data_type),
+            Expr::Identifier(ident) => Ok(Operand::Column(self.column_ref(&ident.value)?)),
+            Expr::BinaryOp { .. } => Ok(Operand::Arithmetic(Box::new(
+                self.compile_arithmetic(expr)?,
+            ))),
+            Expr::Value(_)
+            | Expr::UnaryOp {
+                op: UnaryOperator::Minus,
+                ..
+            } => Ok(Operand::Literal(self.encrypt_literal(expr, data_type)?)),
+            other => Err(QueryError::Unsupported(format!("operand `{}`", other))),
+        }
+    }
+
+    // Type of the value of an expression: the type of the column it names, or the type of an
+    // arithmetic result, promoted from the types of its columns. Literals take the type of what
+    // they are compared with, so they have none.
+    fn expr_type(&self, expr: &Expr) -> Option<DataType> {
+        match expr {
+            Expr::Nested(inner) => self.expr_type(inner),
+            Expr::Identifier(ident) => self
+                .table
+                .column(&ident.value)
+                .map(|column| column.data_type),
+            Expr::BinaryOp { left, op, right }
+                if ArithmeticOp::from_binary_operator(op).is_some() =>
+            {
+                let integer_type = |expr: &Expr| match self.expr_type(expr) {
+                    Some(DataType::Integer(integer_type)) => Some(integer_type),
+                    _ => None,
+                };
+                match (integer_type(left), integer_type(right)) {
+                    (Some(left), Some(right)) => Some(DataType::Integer(left.promote(right))),
+                    (Some(integer_type), None) | (None, Some(integer_type)) => {
+                        Some(DataType::Integer(integer_type))
+                    }
+                    (None, None) => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // Encrypts a literal with the FHE type of the column it is compared with. A negated number
+    // such as `-100` is a literal too.
+    fn encrypt_literal(
+        &self,
+        expr: &Expr,
+        data_type: Option<DataType>,
+    ) -> Result<EncryptedLiteral, QueryError> {
+        let (value, negative) = match expr {
+            Expr::Nested(inner) => return self.encrypt_literal(inner, data_type),
+            Expr::Value(value) => (value, false),
+            Expr::UnaryOp {
+                op: UnaryOperator::Minus,
+                expr,
+            } => match expr.as_ref() {
+                Expr::Value(value @ ast::Value::Number(..)) => (value, true),
+                _ => return Err(QueryError::Unsupported(format!("literal `{}`", expr))),
+            },
+            other => return Err(QueryError::Unsupported(format!("literal `{}`", other))),
+        };
+        let data_type = data_type.ok_or_else(|| {
+            QueryError::Unsupported(format!("literal `{}` not compared with a column", expr))
+        })?;
+        match (value, data_type) {
+            (ast::Value::Number(digits, _), DataType::Integer(integer_type)) => {
+                let num = digits.parse::<i128>().map_err(|_| {
+                    QueryError::Unsupported(format!("numeric literal `{}`", digits))
+                })?;
+                Ok(EncryptedLiteral::Integer(encrypt_integer(
+                    if negative { -num } else { num },
+                    integer_type,
+                    self.client_key,
+                )?))
+            }
+            (ast::Value::SingleQuotedString(s), DataType::String) => Ok(EncryptedLiteral::String(
+                encrypt_string(s, self.string_length, self.client_key)?,
+            )),
+            (ast::Value::Boolean(b), DataType::Boolean) => Ok(EncryptedLiteral::Boolean(
+                FheBool::encrypt(*b, self.client_key),
+            )),
+            (value, data_type) => Err(QueryError::Unsupported(format!(
+                "literal `{}` compared with a {} column",
+                value, data_type
+            ))),
+        }
+    }
+
+    // A column named in clear would give the table away, so it is encrypted in oblivious mode.
+    fn column_ref(&mut self, name: &str) -> Result<ColumnRef, QueryError> {
+        match self.access {
+            TableAccess::Oblivious => Ok(ColumnRef::Slot(self.column_slot(name)?)),
+            TableAccess::Clear => match self.table.column_index(name) {
+                Some(_) => Ok(ColumnRef::Name(name.to_string())),
+                None => Err(QueryError::UnknownColumn(name.to_string())),
+            },
+        }
+    }
+}
+
+fn is_column(expr: &Expr) -> bool {
+    match expr {
+        Expr::Nested(inner) => is_column(inner),
+        Expr::Identifier(_) => true,
+        _ => false,
+    }
+}
+
+// A literal, including a negated number such as `-100`.
+fn is_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Nested(inner) => is_literal(inner),
+        Expr::Value(_) => true,
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => matches!(expr.as_ref(), Expr::Value(ast::Value::Number(..))),
+        _ => false,
+    }
+}
+
+// First clause of a SELECT that is not supported, if any.
+fn unsupported_clause(query: &ast::Query, select: &ast::Select) -> Option<&'static str> {
+    let group_by = match &select.group_by {
+        GroupByExpr::All => true,
+        GroupByExpr::Expressions(expressions) => !expressions.is_empty(),
+    };
+    [
+        (query.with.is_some(), "WITH"),
+        (!query.order_by.is_empty(), "ORDER BY"),
+        (query.limit.is_some() || !query.limit_by.is_empty(), "LIMIT"),
+        (query.offset.is_some(), "OFFSET"),
+        (query.fetch.is_some(), "FETCH"),
+        (!query.locks.is_empty() || query.for_clause.is_some(), "FOR"),
+        (select.top.is_some(), "TOP"),
+        (select.into.is_some(), "INTO"),
+        (!select.lateral_views.is_empty(), "LATERAL VIEW"),
+        (group_by, "GROUP BY"),
+        (
+            !select.cluster_by.is_empty()
+                || !select.distribute_by.is_empty()
+                || !select.sort_by.is_empty(),
+            "CLUSTER BY",
+        ),
+        (select.having.is_some(), "HAVING"),
+        (!select.named_window.is_empty(), "WINDOW"),
+        (select.qualify.is_some(), "QUALIFY"),
+        (select.connect_by.is_some(), "CONNECT BY"),
+        (select.value_table_mode.is_some(), "SELECT AS"),
+    ]
+    .into_iter()
+    .find_map(|(present, clause)| present.then_some(clause))
+}
+
+// Table and column positions are encrypted as `FheUint8`.
+fn to_position(position: usize) -> Result<u8, QueryError> {
+    u8::try_from(position)
+        .map_err(|_| QueryError::Unsupported(format!("position {} beyond 255", position)))
+}
+
+// Encrypts an integer literal at the width and signedness of the column it is compared with, so
+// that the server compares signed columns with two's-complement semantics. A literal the column
+// cannot hold, such as `-1` against a `uint8` column, is encrypted with the narrowest type that
+// holds both instead, so that it still compares the way SQLite compares it.
+fn encrypt_integer(
+    value: i128,
+    integer_type: IntegerType,
+    client_key: &ClientKey,
+) -> Result<EncryptedInteger, QueryError> {
+    let integer_type = match IntegerType::narrowest(value) {
+        Some(narrowest) if !integer_type.holds(value) => integer_type.promote(narrowest),
+        _ => integer_type,
+    };
+    if !integer_type.holds(value) {
+        return Err(QueryError::Unsupported(format!(
+            "literal {} out of the int64 range",
+            value
+        )));
+    }
+    Ok(match (integer_type.is_signed(), integer_type.bits()) {
+        (false, 8) => EncryptedInteger::U8(FheUint8::encrypt(value as u8, client_key)),
+        (false, 16) => EncryptedInteger::U16(FheUint16::encrypt(value as u16, client_key)),
+        (false, 32) => EncryptedInteger::U32(FheUint32::encrypt(value as u32, client_key)),
+        (false, _) => EncryptedInteger::U64(FheUint64::encrypt(value as u64, client_key)),
+        (true, 8) => EncryptedInteger::I8(FheInt8::encrypt(value as i8, client_key)),
+        (true, 16) => EncryptedInteger::I16(FheInt16::encrypt(value as i16, client_key)),
+        (true, 32) => EncryptedInteger::I32(FheInt32::encrypt(value as i32, client_key)),
+        (true, _) => EncryptedInteger::I64(FheInt64::encrypt(value as i64, client_key)),
+    })
+}
+
+// Encrypts a string as `length` bytes, zero-padded so that its length stays hidden.
+fn encrypt_string(
+    value: &str,
+    length: usize,
+    client_key: &ClientKey,
+) -> Result<Vec<FheUint8>, QueryError> {
+    if value.len() > length {
+        return Err(QueryError::Unsupported(format!(
+            "string literal longer than {} bytes",
+            length
+        )));
+    }
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.resize(length, 0);
+    Ok(bytes
+        .into_iter()
+        .map(|byte| FheUint8::encrypt(byte, client_key))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::ParameterProfile;
+
+    fn schema() -> Schema {
+        let table = TableSchema::from_headers(
+            Path::new("t.csv"),
+            ["id:uint8", "name:string", "other:string", "flag:bool"],
+        )
+        .unwrap();
+        Schema {
+            tables: vec![table],
+        }
+    }
+
+    // The reason a query is rejected as unsupported.
+    fn rejection(client: &Client, sql: &str) -> String {
+        match client.encrypt_query(sql, &schema(), TableAccess::Clear) {
+            Err(QueryError::Unsupported(what)) => what,
+            Err(err) => panic!("{}: {}", sql, err),
+            Ok(_) => panic!("{}: compiled", sql),
+        }
+    }
+
+    #[test]
+    fn rejects_what_the_server_cannot_run() {
+        let (client, _) = Client::new(ParameterProfile::Default.config());
+        let cases = [
+            ("", "empty query"),
+            (
+                "SELECT id FROM t; SELECT id FROM t",
+                "more than one statement",
+            ),
+            ("SELECT id FROM t ORDER BY id", "ORDER BY"),
+            ("SELECT id FROM t LIMIT 1", "LIMIT"),
+            ("SELECT id FROM t OFFSET 1", "OFFSET"),
+            ("SELECT id FROM t GROUP BY id", "GROUP BY"),
+            ("SELECT id FROM t GROUP BY id HAVING id > 1", "GROUP BY"),
+            ("WITH x AS (SELECT id FROM t) SELECT id FROM t", "WITH"),
+            (
+                "SELECT id FROM t WHERE 1 BETWEEN id AND 2",
+                "BETWEEN must compare a column with two literals",
+            ),
+            (
+                "SELECT id FROM t WHERE id BETWEEN id AND 2",
+                "BETWEEN must compare a column with two literals",
+            ),
+            (
+                "SELECT id FROM t WHERE name BETWEEN 'a' AND 'b'",
+                "BETWEEN on strings `name`",
+            ),
+            (
+                "SELECT id FROM t WHERE name < 'a'",
+                "`name < 'a'`: strings only compare with = and <>",
+            ),
+            (
+                "SELECT id FROM t WHERE name = other",
+                "`name = other`: comparison between a string and another column",
+            ),
+        ];
+        for (sql, reason) in cases {
+            assert_eq!(rejection(&client, sql), reason, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn rejects_expressions_nested_too_deeply() {
+        let (client, _) = Client::new(ParameterProfile::Default.config());
+        let sql = |depth: usize| {
+            format!(
+                "SELECT id FROM t WHERE id = 1{}",
+                " AND id = 1".repeat(depth - 1)
+            )
+        };
+        assert!(client
+            .encrypt_query(&sql(MAX_DEPTH), &schema(), TableAccess::Clear)
+            .is_ok());
+        assert_eq!(
+            rejection(&client, &sql(MAX_DEPTH + 1)),
+            format!("expressions nested deeper than {}", MAX_DEPTH)
+        );
+    }
+}
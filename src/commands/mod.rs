@@ -0,0 +1,376 @@
+//! Subcommands of the binary. The client commands (`keygen`, `encrypt-query`, `decrypt-result`)
+//! and the server command (`serve-query`) only exchange files, so the server can run on a
+//! machine that never sees the client key. `listen` and `query` do the same over TCP. Without a
+//! subcommand, the whole round trip runs in one process and is checked against SQLite.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+
+use crate::client::{Client, ResultLayout, TableAccess};
+use crate::database_server::Database;
+use crate::keys;
+use crate::parameters::{ParameterProfile, PROFILES};
+use crate::query::{EncryptedQuery, DEFAULT_STRING_LENGTH};
+use crate::schema::Schema;
+use crate::server;
+use crate::service::{self, Connection, Service};
+use crate::wire;
+
+const USAGE: &str = "\
+Usage: {bin} ./db_dir query.txt [QUERY_OPTIONS] [--params PROFILE] [--keys key_dir]
+       {bin} keygen key_dir [--params PROFILE] [--compressed]
+       {bin} encrypt-query key_dir db_dir query.txt query.enc [QUERY_OPTIONS]
+       {bin} serve-query db_dir query.enc key_dir result.enc
+       {bin} decrypt-result key_dir result.enc query.layout
+       {bin} listen db_dir [--address ADDRESS]
+       {bin} query key_dir db_dir query.txt [--address ADDRESS] [QUERY_OPTIONS]
+QUERY_OPTIONS are:
+  --clear-table      name the table in clear: faster, but the server learns which one it is
+  --max-rows N       number of rows of the result, at most and by default the row count of the
+                     largest table
+  --string-length N  number of bytes string literals are padded to, {string_length} by default
+PROFILE is one of: {profiles}
+ADDRESS defaults to {address}";
+
+pub(crate) fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let command = args.get(1).map(String::as_str).unwrap_or_default();
+    let rest = args.get(2..).unwrap_or_default();
+    match command {
+        "keygen" => keygen(&Arguments::parse(
+            args,
+            rest,
+            1,
+            &["--compressed"],
+            &["--params"],
+        )),
+        "encrypt-query" => encrypt_query(&Arguments::parse(
+            args,
+            rest,
+            4,
+            &["--clear-table"],
+            &["--max-rows", "--string-length"],
+        )),
+        "serve-query" => serve_query(&Arguments::parse(args, rest, 4, &[], &[])),
+        "decrypt-result" => decrypt_result(&Arguments::parse(args, rest, 3, &[], &[])),
+        "listen" => listen(&Arguments::parse(args, rest, 1, &[], &["--address"])),
+        "query" => query(&Arguments::parse(
+            args,
+            rest,
+            3,
+            &["--clear-table"],
+            &["--address", "--max-rows", "--string-length"],
+        )),
+        _ => round_trip(&Arguments::parse(
+            args,
+            args.get(1..).unwrap_or_default(),
+            2,
+            &["--clear-table"],
+            &["--params", "--keys", "--max-rows", "--string-length"],
+        )),
+    }
+}
+
+// Generates a keypair and writes it to a directory. The server key can then be copied alone to
+// the server.
+fn keygen(args: &Arguments) -> Result<(), Box<dyn Error>> {
+    let dir = args.path(0);
+    let profile = args.profile()?.unwrap_or_default();
+
+    let start = Instant::now();
+    let (client, server_key) = Client::new(profile.config());
+    client.save(dir, profile)?;
+    let compressed_key = args
+        .has("--compressed")
+        .then(|| client.compressed_server_key());
+    keys::save_server_key(dir, profile, &server_key, compressed_key.as_ref())?;
+    println!(
+        "Keys generated in {:.2?} with the {} parameters, saved to {}",
+        start.elapsed(),
+        profile,
+        dir.display()
+    );
+
+    Ok(())
+}
+
+// Client: encrypts a query against the schema of the database, read from its CSV headers. The
+// layout needed to decrypt the result is written next to the query and must stay on the client.
+fn encrypt_query(args: &Arguments) -> Result<(), Box<dyn Error>> {
+    let (key_dir, db_path, query_path, output) =
+        (args.path(0), args.path(1), args.path(2), args.path(3));
+
+    let (mut client, profile) = Client::load(key_dir, None)?;
+    let schema = Database::read_schema(db_path)?;
+    let query = fs::read_to_string(query_path)?;
+    let (encrypted_query, layout) = encrypt(args, &mut client, &query, &schema)?;
+
+    wire::write_query_file(output, &encrypted_query, profile)?;
+    let layout_path = layout_path(output);
+    serde_json::to_writer(BufWriter::new(File::create(&layout_path)?), &layout)?;
+    println!(
+        "{} written to {}, result layout to {}",
+        encrypted_query,
+        output.display(),
+        layout_path.display()
+    );
+
+    Ok(())
+}
+
+// Server: runs an encrypted query on the database with the server key alone.
+fn serve_query(args: &Arguments) -> Result<(), Box<dyn Error>> {
+    let (db_path, query_path, key_dir, output) =
+        (args.path(0), args.path(1), args.path(2), args.path(3));
+
+    let (server_key, profile) = keys::load_server_key(key_dir, None)?;
+    let encrypted_query = wire::read_query_file(query_path, profile)?;
+    let tables = Database::load_from_directory(db_path)?.to_tables()?;
+
+    let start = Instant::now();
+    let encrypted_result = server::run_fhe_query(&server_key, &encrypted_query, &tables)?;
+    let duration = start.elapsed();
+
+    wire::write_result_file(output, &encrypted_result, profile)?;
+    println!("Runtime: {:.2?} (parameters: {})", duration, profile);
+    println!("Encrypted result written to {}", output.display());
+
+    Ok(())
+}
+
+// Client: decrypts a result written by `serve-query`.
+fn decrypt_result(args: &Arguments) -> Result<(), Box<dyn Error>> {
+    let (key_dir, result_path, layout_path) = (args.path(0), args.path(1), args.path(2));
+
+    let (client, profile) = Client::load(key_dir, None)?;
+    let encrypted_result = wire::read_result_file(result_path, profile)?;
+    let layout: ResultLayout = serde_json::from_reader(BufReader::new(File::open(layout_path)?))?;
+    let decrypted_result = client.decrypt_result(&encrypted_result, &layout);
+    println!("Encrypted DB query result: {}", decrypted_result);
+
+    Ok(())
+}
+
+// Server: loads the database once and serves encrypted queries over TCP until killed.
+fn listen(args: &Arguments) -> Result<(), Box<dyn Error>> {
+    let db_path = args.path(0);
+    let address = args.value("--address").unwrap_or(service::DEFAULT_ADDRESS);
+
+    let tables = Database::load_from_directory(db_path)?.to_tables()?;
+    let service = Service::bind(address, tables)?;
+    println!("Listening on {}", service.local_addr()?);
+    service.run()?;
+
+    Ok(())
+}
+
+// Client: sends the server key and an encrypted query to a `listen` server, and decrypts the
+// result it sends back.
+fn query(args: &Arguments) -> Result<(), Box<dyn Error>> {
+    let (key_dir, db_path, query_path) = (args.path(0), args.path(1), args.path(2));
+    let address = args.value("--address").unwrap_or(service::DEFAULT_ADDRESS);
+
+    let (mut client, profile) = Client::load(key_dir, None)?;
+    let (server_key, _) = keys::load_server_key(key_dir, Some(profile))?;
+    let schema = Database::read_schema(db_path)?;
+    let query = fs::read_to_string(query_path)?;
+    let (encrypted_query, layout) = encrypt(args, &mut client, &query, &schema)?;
+
+    let start = Instant::now();
+    let mut connection = Connection::open(address, &server_key, profile)?;
+    let encrypted_result = connection.query(&encrypted_query)?;
+    let duration = start.elapsed();
+
+    let decrypted_result = client.decrypt_result(&encrypted_result, &layout);
+    println!("Round trip: {:.2?} (parameters: {})", duration, profile);
+    println!("Encrypted DB query result: {}", decrypted_result);
+
+    Ok(())
+}
+
+// Encrypts, runs and decrypts a query in one process, and checks the result against SQLite.
+fn round_trip(args: &Arguments) -> Result<(), Box<dyn Error>> {
+    let (db_path, query_file_path) = (args.path(0), args.path(1));
+    let requested_profile = args.profile()?;
+
+    // Setup TFHE configuration: reuse saved keys if there are any, otherwise generate fresh ones.
+    let (mut client, server_key, profile) = match args.value("--keys") {
+        Some(dir) => {
+            let dir = Path::new(dir);
+            let (client, profile) = Client::load(dir, requested_profile)?;
+            let (server_key, _) = keys::load_server_key(dir, Some(profile))?;
+            (client, server_key, profile)
+        }
+        None => {
+            let profile = requested_profile.unwrap_or_default();
+            let (client, server_key) = Client::new(profile.config());
+            (client, server_key, profile)
+        }
+    };
+    println!("Parameters: {}", profile);
+
+    // Load the database
+    let db = Database::load_from_directory(db_path)?;
+    for entry in &db.catalog().entries {
+        println!(
+            "Table {}: {} row(s) from {}",
+            entry.name(),
+            entry.row_count,
+            entry.source.display()
+        );
+    }
+
+    // Load and encrypt the query against the public schema of the database
+    let query = fs::read_to_string(query_file_path)?;
+    let (encrypted_query, layout) = encrypt(args, &mut client, &query, &db.schema())?;
+    println!("Encrypted Query: {}", encrypted_query);
+
+    // Convert or access Tables from Database
+    let tables = db.to_tables()?;
+
+    // Run an FHE query.
+    let start = Instant::now();
+    let encrypted_result = server::run_fhe_query(&server_key, &encrypted_query, &tables)?;
+    let duration = start.elapsed();
+
+    // Decrypt the result
+    let decrypted_result = client.decrypt_result(&encrypted_result, &layout);
+
+    // Run the same query in clear as a reference
+    let clear_result = db.run_query(&query, &layout.column_types())?;
+    let diff = clear_result.diff(&decrypted_result);
+
+    println!("Runtime: {:.2?} (parameters: {})", duration, profile);
+    println!("Clear DB query result: {}", clear_result);
+    println!("Encrypted DB query result: {}", decrypted_result);
+    if diff.is_empty() {
+        println!("Results match: YES");
+    } else {
+        println!("Results match: NO");
+        print!("{}", diff);
+    }
+
+    Ok(())
+}
+
+// Encrypts a query with the query options given on the command line.
+fn encrypt(
+    args: &Arguments,
+    client: &mut Client,
+    query: &str,
+    schema: &Schema,
+) -> Result<(EncryptedQuery, ResultLayout), Box<dyn Error>> {
+    if let Some(string_length) = args.number("--string-length")? {
+        client.set_string_length(string_length);
+    }
+    let (mut encrypted_query, layout) = client.encrypt_query(query, schema, args.table_access())?;
+    encrypted_query.max_rows = args.number("--max-rows")?;
+    Ok((encrypted_query, layout))
+}
+
+// The layout of `query.enc` is kept in `query.layout`.
+fn layout_path(query_path: &Path) -> PathBuf {
+    query_path.with_extension("layout")
+}
+
+// Positional arguments and options of a subcommand. Invalid arguments print the usage and exit.
+struct Arguments<'a> {
+    positional: Vec<&'a str>,
+    switches: Vec<&'a str>,
+    values: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Arguments<'a> {
+    fn parse(
+        all: &[String],
+        args: &'a [String],
+        positional_count: usize,
+        switches: &[&str],
+        options: &[&str],
+    ) -> Arguments<'a> {
+        let mut parsed = Arguments {
+            positional: Vec::new(),
+            switches: Vec::new(),
+            values: Vec::new(),
+        };
+        let mut args = args.iter().map(String::as_str);
+        while let Some(arg) = args.next() {
+            if switches.contains(&arg) {
+                parsed.switches.push(arg);
+            } else if options.contains(&arg) {
+                match args.next() {
+                    Some(value) => parsed.values.push((arg, value)),
+                    None => usage(all, &format!("{} expects a value", arg)),
+                }
+            } else if arg.starts_with("--") {
+                usage(all, &format!("Unknown option: {}", arg));
+            } else {
+                parsed.positional.push(arg);
+            }
+        }
+        if parsed.positional.len() != positional_count {
+            usage(all, "Wrong number of arguments");
+        }
+        parsed
+    }
+
+    fn path(&self, index: usize) -> &'a Path {
+        Path::new(self.positional[index])
+    }
+
+    fn has(&self, switch: &str) -> bool {
+        self.switches.contains(&switch)
+    }
+
+    fn value(&self, option: &str) -> Option<&'a str> {
+        self.values
+            .iter()
+            .find(|(name, _)| *name == option)
+            .map(|&(_, value)| value)
+    }
+
+    fn profile(&self) -> Result<Option<ParameterProfile>, String> {
+        match self.value("--params") {
+            Some(name) => ParameterProfile::from_name(name)
+                .map(Some)
+                .ok_or_else(|| format!("Unknown parameter profile: {}", name)),
+            None => Ok(None),
+        }
+    }
+
+    fn number(&self, option: &str) -> Result<Option<usize>, String> {
+        match self.value(option) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("{} expects a number, found {}", option, value)),
+            None => Ok(None),
+        }
+    }
+
+    // Naming the table in clear is faster, but reveals to the server which table is queried.
+    fn table_access(&self) -> TableAccess {
+        if self.has("--clear-table") {
+            TableAccess::Clear
+        } else {
+            TableAccess::Oblivious
+        }
+    }
+}
+
+fn usage(args: &[String], error: &str) -> ! {
+    let profiles: Vec<&str> = PROFILES.iter().map(|profile| profile.name()).collect();
+    eprintln!("{}", error);
+    eprintln!(
+        "{}",
+        USAGE
+            .replace("{bin}", args.first().map_or("encrypt_sql", String::as_str))
+            .replace("{profiles}", &profiles.join(", "))
+            .replace("{address}", service::DEFAULT_ADDRESS)
+            .replace("{string_length}", &DEFAULT_STRING_LENGTH.to_string())
+    );
+    process::exit(1);
+}
@@ -1,16 +1,26 @@
-use std::collections::HashMap;
 use std::fmt;
 use std::fs::read_dir;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::Tables;
-use rusqlite::{Connection, Result};
+use crate::results::ResultSet;
+use crate::schema::{Column, DataType, Row, Schema, SchemaError, TableSchema, Value, MAX_TABLES};
+use crate::tables::{Table, Tables};
+use rusqlite::types::{self, ValueRef};
+use rusqlite::{params_from_iter, Connection, Result};
 
 #[derive(Debug)]
 pub(crate) enum AppError {
     Sqlite(rusqlite::Error),
     Io(std::io::Error),
     Csv(csv::Error),
+    Schema(SchemaError),
+    UnknownTable(String),
+    // Two CSV files define the same table.
+    DuplicateTable { name: String, source: PathBuf },
+    // The clear query returned another number of columns than the encrypted one projects.
+    ResultShape { expected: usize, found: usize },
+    // More tables than encrypted positions can address.
+    TooManyTables(usize),
 }
 
 impl fmt::Display for AppError {
@@ -19,10 +29,31 @@ impl fmt::Display for AppError {
             AppError::Sqlite(ref err) => write!(f, "SQLite error: {}", err),
             AppError::Io(ref err) => write!(f, "IO error: {}", err),
             AppError::Csv(ref err) => write!(f, "CSV error: {}", err),
+            AppError::Schema(ref err) => write!(f, "Schema error: {}", err),
+            AppError::UnknownTable(ref name) => write!(f, "Unknown table: {}", name),
+            AppError::DuplicateTable {
+                ref name,
+                ref source,
+            } => write!(
+                f,
+                "Table {} is already loaded from {}",
+                name,
+                source.display()
+            ),
+            AppError::ResultShape { expected, found } => write!(
+                f,
+                "Clear query returned {} column(s), expected {}",
+                found, expected
+            ),
+            AppError::TooManyTables(count) => {
+                write!(f, "{} tables, at most {} are supported", count, MAX_TABLES)
+            }
         }
     }
 }
 
+impl std::error::Error for AppError {}
+
 impl From<rusqlite::Error> for AppError {
     fn from(err: rusqlite::Error) -> AppError {
         AppError::Sqlite(err)
@@ -41,138 +72,185 @@ impl From<csv::Error> for AppError {
     }
 }
 
+impl From<SchemaError> for AppError {
+    fn from(err: SchemaError) -> AppError {
+        AppError::Schema(err)
+    }
+}
+
 pub(crate) struct Database {
     conn: Connection,
+    // Every table loaded from a CSV file. The database holds no other table.
+    catalog: Catalog,
+}
+
+/// The user tables of a `Database`, in load order, which is the order of their positions.
+#[derive(Debug, Default)]
+pub(crate) struct Catalog {
+    pub entries: Vec<CatalogEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CatalogEntry {
+    pub schema: TableSchema,
+    // CSV file the table was loaded from.
+    pub source: PathBuf,
+    pub row_count: usize,
+}
+
+impl Catalog {
+    pub fn entry(&self, name: &str) -> Option<&CatalogEntry> {
+        self.entries.iter().find(|entry| entry.name() == name)
+    }
+
+    // Public schema of the tables, shared with the client.
+    pub fn schema(&self) -> Schema {
+        Schema {
+            tables: self
+                .entries
+                .iter()
+                .map(|entry| entry.schema.clone())
+                .collect(),
+        }
+    }
+}
+
+impl CatalogEntry {
+    pub fn name(&self) -> &str {
+        &self.schema.name
+    }
 }
 
 impl Database {
     pub fn new() -> Result<Database, AppError> {
         let conn = Connection::open_in_memory()?;
-        conn.execute(
-            "CREATE TABLE integers (id INTEGER PRIMARY KEY, value INTEGER);",
-            [],
-        )?;
-        conn.execute(
-            "CREATE TABLE booleans (id INTEGER PRIMARY KEY, value BOOLEAN);",
-            [],
-        )?;
-        Ok(Database { conn })
+        Ok(Database {
+            conn,
+            catalog: Catalog::default(),
+        })
     }
 
     pub fn load_from_directory(path: &Path) -> Result<Database, AppError> {
-        let db = Database::new()?;
+        let mut db = Database::new()?;
         println!("Database initialized in memory.");
 
-        let entries = read_dir(path).map_err(AppError::Io)?;
-        for entry in entries {
-            let entry = entry.map_err(AppError::Io)?;
-            let file_path = entry.path();
-            println!("Considering file: {}", file_path.display());
-
-            if file_path.is_file() && file_path.extension().unwrap_or_default() == "csv" {
-                println!("Loading CSV file: \"{}\"", file_path.display());
-                db.load_table_from_csv(&file_path)?;
-            }
+        for file_path in csv_files(path)? {
+            println!("Loading CSV file: \"{}\"", file_path.display());
+            db.load_table_from_csv(&file_path)?;
         }
 
         Ok(db)
     }
 
-    // Load a table from a CSV file in the directory.
-    pub fn load_table_from_csv(&self, file_path: &Path) -> Result<(), AppError> {
-        let mut reader = csv::Reader::from_path(file_path)?;
-        let headers = reader
-            .headers()?
-            .iter()
-            .map(|h| h.split(':').next().unwrap().to_string())
-            .collect::<Vec<String>>();
-
-        let table_name = file_path.file_stem().unwrap().to_str().unwrap();
-        println!("Processing CSV for table: {}", table_name);
-
-        self.ensure_table(&headers, file_path)?;
-
-        for record in reader.records() {
-            let record = record?;
-            let values: Vec<String> = record
-                .iter()
-                .map(|value| format!("'{}'", value.replace("'", "''")))
-                .collect();
-            let sql = format!(
-                "INSERT INTO {} ({}) VALUES ({});",
-                table_name,
-                headers.join(","),
-                values.join(",")
-            );
-            println!("Executing SQL: {}", sql);
-            self.conn.execute(&sql, []).map_err(AppError::Sqlite)?;
+    // Reads the schema of the tables of a directory from the CSV headers alone, in the order
+    // `load_from_directory` gives them. This is all a client needs to encrypt queries.
+    pub fn read_schema(path: &Path) -> Result<Schema, AppError> {
+        let mut schema = Schema::default();
+        for file_path in csv_files(path)? {
+            let mut reader = csv::Reader::from_path(&file_path)?;
+            let table = TableSchema::from_headers(&file_path, reader.headers()?.iter())?;
+            if let Some(existing) = schema.table(&table.name) {
+                return Err(AppError::DuplicateTable {
+                    name: existing.name.clone(),
+                    source: file_path,
+                });
+            }
+            schema.tables.push(table);
         }
-
-        Ok(())
+        Ok(schema)
     }
 
-    // Ensure a table exists with the appropriate columns and types based on CSV headers
-    pub fn ensure_table(&self, headers: &[String], file_path: &Path) -> Result<(), AppError> {
-        let table_name = file_path.file_stem().unwrap().to_str().unwrap();
-        println!("Ensuring table structure for: {}", table_name);
+    // Load a table from a CSV file in the directory. Every header must be `name:type` and every
+    // value must parse as its column's type, otherwise nothing is loaded from the file. The rows
+    // are inserted in a single transaction, with their values bound as typed parameters.
+    pub fn load_table_from_csv(&mut self, file_path: &Path) -> Result<(), AppError> {
+        let mut reader = csv::Reader::from_path(file_path)?;
+        let table = TableSchema::from_headers(file_path, reader.headers()?.iter())?;
+        println!("Processing CSV for table: {}", table.name);
+        if let Some(entry) = self.catalog.entry(&table.name) {
+            return Err(AppError::DuplicateTable {
+                name: table.name,
+                source: entry.source.clone(),
+            });
+        }
 
-        let sql_check_table_exists =
-            format!("SELECT name FROM sqlite_master WHERE type='table' AND name=?;");
-        let table_exists: bool = self
-            .conn
-            .query_row(&sql_check_table_exists, &[table_name], |_| Ok(()))
-            .is_ok();
+        let rows = reader
+            .records()
+            .map(|record| {
+                let record = record?;
+                let line = record.position().map_or(0, |position| position.line());
+                table
+                    .parse_row(file_path, line, &record.iter().collect::<Vec<_>>())
+                    .map_err(AppError::Schema)
+            })
+            .collect::<Result<Vec<Row>, AppError>>()?;
 
-        if !table_exists {
-            println!("Table does not exist. Creating new table: {}", table_name);
+        let transaction = self.conn.transaction()?;
+        ensure_table(&transaction, &table)?;
 
-            let column_definitions: Vec<String> = headers
-                .iter()
-                .map(|header| {
-                    let parts: Vec<&str> = header.split(':').collect();
-                    format!(
-                        "{} {}",
-                        parts[0],
-                        match parts.get(1) {
-                            Some(&"uint32") | Some(&"int32") | Some(&"uint16") | Some(&"int16")
-                            | Some(&"uint8") | Some(&"int8") => "INTEGER",
-                            Some(&"bool") => "BOOLEAN",
-                            Some(&"string") => "TEXT",
-                            _ => "TEXT",
-                        }
-                    )
-                })
-                .collect();
-
-            let columns_sql = column_definitions.join(", ");
-            let sql_create_table = format!("CREATE TABLE {} ({});", table_name, columns_sql);
-            self.conn
-                .execute(&sql_create_table, [])
-                .map_err(AppError::Sqlite)?;
-            println!("Table created successfully: {}", table_name);
-        } else {
-            println!("Table {} already exists. Skipping creation.", table_name);
+        let headers = table
+            .columns
+            .iter()
+            .map(|column| quote_identifier(&column.name))
+            .collect::<Vec<String>>();
+        let placeholders = vec!["?"; headers.len()];
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES ({});",
+            quote_identifier(&table.name),
+            headers.join(","),
+            placeholders.join(",")
+        );
+        println!("Executing SQL: {} for {} row(s)", sql, rows.len());
+        {
+            let mut stmt = transaction.prepare(&sql)?;
+            for row in &rows {
+                stmt.execute(params_from_iter(row.values.iter().map(sql_value)))?;
+            }
         }
+        transaction.commit()?;
+        self.catalog.entries.push(CatalogEntry {
+            schema: table,
+            source: file_path.to_path_buf(),
+            row_count: rows.len(),
+        });
 
         Ok(())
     }
 
-    // Retrieves data from a named table and converts each row into a HashMap.
-    pub fn retrieve_table_data(
-        &self,
-        table_name: &str,
-    ) -> Result<Vec<HashMap<String, String>>, AppError> {
+    // Retrieves data from a named table, every column read back with its declared type.
+    pub fn retrieve_table_data(&self, table_name: &str) -> Result<Vec<Row>, AppError> {
+        let table = &self
+            .catalog
+            .entry(table_name)
+            .ok_or_else(|| AppError::UnknownTable(table_name.to_string()))?
+            .schema;
         let mut stmt = self
             .conn
-            .prepare(&format!("SELECT * FROM {}", table_name))
+            .prepare(&format!("SELECT * FROM {}", quote_identifier(table_name)))
             .map_err(AppError::Sqlite)?;
 
+        // Position of each schema column among the columns of the statement.
+        let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        let positions = table
+            .columns
+            .iter()
+            .map(|column| {
+                names
+                    .iter()
+                    .position(|name| *name == column.name)
+                    .ok_or_else(|| rusqlite::Error::InvalidColumnName(column.name.clone()))
+            })
+            .collect::<Result<Vec<usize>, _>>()?;
+
         let rows = stmt
             .query_map([], |row| {
-                let mut result = HashMap::new();
-                // Assume columns are known or dynamically determined.
-                result.insert("id".to_string(), row.get::<_, String>("id").unwrap());
-                Ok(result)
+                let values = table
+                    .columns
+                    .iter()
+                    .zip(&positions)
+                    .map(|(column, &position)| read_value(row.get_ref(position)?, position, column))
+                    .collect::<Result<Vec<Value>, _>>()?;
+                Ok(Row { values })
             })
             .map_err(AppError::Sqlite)?;
 
@@ -181,26 +259,237 @@ impl Database {
             .collect()
     }
 
-    // Additional method to convert the database content to the Tables structure
-    pub fn to_tables(&self) -> Result<Tables, AppError> {
-        let mut tables = Tables::new();
+    // Runs a query in clear, reading each result column with the given type. This is the
+    // reference the decrypted result is checked against.
+    pub fn run_query(&self, sql: &str, types: &[DataType]) -> Result<ResultSet, AppError> {
+        let mut stmt = self.conn.prepare(sql)?;
+        if stmt.column_count() != types.len() {
+            return Err(AppError::ResultShape {
+                expected: types.len(),
+                found: stmt.column_count(),
+            });
+        }
+        let columns: Vec<Column> = stmt
+            .column_names()
+            .into_iter()
+            .zip(types)
+            .map(|(name, &data_type)| Column {
+                name: name.to_string(),
+                data_type,
+            })
+            .collect();
 
-        // First, get all table names from the database
-        let mut stmt = self
-            .conn
-            .prepare("SELECT name FROM sqlite_master WHERE type='table';")?;
-        let table_names = stmt.query_map([], |row| {
-            let name: String = row.get(0)?;
-            Ok(name)
+        let rows = stmt.query_map([], |row| {
+            let values = columns
+                .iter()
+                .enumerate()
+                .map(|(position, column)| {
+                    match (row.get_ref(position)?, column.data_type) {
+                        // A division by zero.
+                        (ValueRef::Null, _) => Ok(Value::Null),
+                        // SQLite computes arithmetic in int64, where it wraps around at the type
+                        // of its result under encryption. The value is kept as SQLite computed
+                        // it, so that an overflow shows up in the diff with the encrypted result.
+                        (ValueRef::Integer(value), DataType::Integer(_)) => {
+                            Ok(Value::Integer(value))
+                        }
+                        // A value of another type than the encrypted result holds, e.g. `+` on
+                        // strings, which SQLite computes as numeric addition, is kept as stored
+                        // so that it shows up in the diff.
+                        (value, _) => read_value(value, position, column)
+                            .or_else(|err| stored_value(value).ok_or(err)),
+                    }
+                })
+                .collect::<Result<Vec<Value>, _>>()?;
+            Ok(Row { values })
         })?;
+        let rows = rows.collect::<Result<Vec<Row>, _>>()?;
+        Ok(ResultSet { rows })
+    }
+
+    // Tables loaded from CSV files, with where they came from.
+    pub fn catalog(&self) -> &Catalog {
+        &self.catalog
+    }
+
+    // Public schema of the loaded tables, shared with the client.
+    pub fn schema(&self) -> Schema {
+        self.catalog.schema()
+    }
+
+    // Additional method to convert the database content to the Tables structure. Only the
+    // tables of the catalog are copied, in catalog order, each stored column by column with its
+    // declared types.
+    pub fn to_tables(&self) -> Result<Tables, AppError> {
+        let mut tables = Tables::new();
 
-        // Iterate over each table name and retrieve its data
-        for table_name in table_names {
-            let table_name = table_name?;
-            let data = self.retrieve_table_data(&table_name)?;
-            tables.tables.insert(table_name, data);
+        for entry in &self.catalog.entries {
+            let rows = self.retrieve_table_data(entry.name())?;
+            tables.insert_table(Table::from_rows(entry.schema.clone(), &rows));
         }
 
         Ok(tables)
     }
 }
+
+// CSV files of a directory, in file name order, which is the order the client encrypts table
+// positions against.
+fn csv_files(path: &Path) -> Result<Vec<PathBuf>, AppError> {
+    let mut file_paths = read_dir(path)
+        .map_err(AppError::Io)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(AppError::Io)?;
+    file_paths.sort();
+    file_paths.retain(|file_path| {
+        file_path.is_file() && file_path.extension().unwrap_or_default() == "csv"
+    });
+    if file_paths.len() > MAX_TABLES {
+        return Err(AppError::TooManyTables(file_paths.len()));
+    }
+    Ok(file_paths)
+}
+
+// Ensure a table exists with the appropriate columns and types based on CSV headers
+fn ensure_table(conn: &Connection, table: &TableSchema) -> Result<(), AppError> {
+    let table_name = table.name.as_str();
+    println!("Ensuring table structure for: {}", table_name);
+
+    let sql_check_table_exists = "SELECT name FROM sqlite_master WHERE type='table' AND name=?;";
+    let table_exists: bool = conn
+        .query_row(sql_check_table_exists, [table_name], |_| Ok(()))
+        .is_ok();
+
+    if !table_exists {
+        println!("Table does not exist. Creating new table: {}", table_name);
+
+        let column_definitions: Vec<String> = table
+            .columns
+            .iter()
+            .map(|column| {
+                format!(
+                    "{} {}",
+                    quote_identifier(&column.name),
+                    match column.data_type {
+                        DataType::Integer(_) => "INTEGER",
+                        DataType::Boolean => "BOOLEAN",
+                        DataType::String => "TEXT",
+                    }
+                )
+            })
+            .collect();
+
+        let columns_sql = column_definitions.join(", ");
+        let sql_create_table = format!(
+            "CREATE TABLE {} ({});",
+            quote_identifier(table_name),
+            columns_sql
+        );
+        conn.execute(&sql_create_table, [])
+            .map_err(AppError::Sqlite)?;
+        println!("Table created successfully: {}", table_name);
+    } else {
+        println!("Table {} already exists. Skipping creation.", table_name);
+    }
+
+    Ok(())
+}
+
+// Quotes a table or column name for SQLite, so that it is never read as SQL.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// Value bound to an INSERT parameter: integers as INTEGER, bools as 0/1 and strings as TEXT.
+fn sql_value(value: &Value) -> types::Value {
+    match value {
+        Value::Null => types::Value::Null,
+        Value::Integer(value) => types::Value::Integer(*value),
+        Value::Boolean(value) => types::Value::Integer(i64::from(*value)),
+        Value::String(value) => types::Value::Text(value.clone()),
+    }
+}
+
+// Converts a SQLite value to the type declared for its column.
+fn read_value(value: ValueRef, position: usize, column: &Column) -> Result<Value, rusqlite::Error> {
+    let converted = match (column.data_type, value) {
+        (DataType::Integer(integer_type), ValueRef::Integer(value))
+            if (integer_type.min_value()..=integer_type.max_value()).contains(&(value as i128)) =>
+        {
+            Some(Value::Integer(value))
+        }
+        (DataType::Boolean, ValueRef::Integer(value)) => Some(Value::Boolean(value != 0)),
+        (DataType::String, ValueRef::Text(text)) => std::str::from_utf8(text)
+            .ok()
+            .map(|text| Value::String(text.to_string())),
+        _ => None,
+    };
+    converted.ok_or_else(|| {
+        rusqlite::Error::InvalidColumnType(position, column.name.clone(), value.data_type())
+    })
+}
+
+// Value as SQLite stores it, whatever the type of its column.
+fn stored_value(value: ValueRef) -> Option<Value> {
+    match value {
+        ValueRef::Null => Some(Value::Null),
+        ValueRef::Integer(value) => Some(Value::Integer(value)),
+        ValueRef::Text(text) => std::str::from_utf8(text)
+            .ok()
+            .map(|text| Value::String(text.to_string())),
+        ValueRef::Real(_) | ValueRef::Blob(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn rejects_more_tables_than_positions() {
+        let dir = std::env::temp_dir().join(format!("encrypt_sql-tables-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for table in 0..MAX_TABLES {
+            fs::write(dir.join(format!("t{}.csv", table)), "id:uint8\n1\n").unwrap();
+        }
+        assert_eq!(
+            Database::read_schema(&dir).unwrap().tables.len(),
+            MAX_TABLES
+        );
+
+        fs::write(dir.join("extra.csv"), "id:uint8\n1\n").unwrap();
+        assert!(matches!(
+            Database::read_schema(&dir),
+            Err(AppError::TooManyTables(257))
+        ));
+        assert!(matches!(
+            Database::load_from_directory(&dir),
+            Err(AppError::TooManyTables(257))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_values_of_another_type_than_expected() {
+        let dir = std::env::temp_dir().join(format!("encrypt_sql-types-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("t.csv"), "first:string,last:string\nab,cd\n").unwrap();
+        let db = Database::load_from_directory(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // SQLite adds strings as numbers, where the encrypted query concatenates them.
+        let result = db
+            .run_query("SELECT first + last FROM t", &[DataType::String])
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            [Row {
+                values: vec![Value::Integer(0)],
+            }]
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Key files. Each key is written with a header naming the key, the file format version and the
+//! parameter profile it was generated with, so that a key is never loaded with the wrong kind or
+//! parameters, or from an incompatible build.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tfhe::{CompressedServerKey, ServerKey};
+
+use crate::parameters::ParameterProfile;
+
+pub(crate) const CLIENT_KEY_FILE: &str = "client_key.bin";
+pub(crate) const SERVER_KEY_FILE: &str = "server_key.bin";
+pub(crate) const COMPRESSED_SERVER_KEY_FILE: &str = "server_key.compressed.bin";
+
+const MAGIC: [u8; 4] = *b"ESQK";
+const FORMAT_VERSION: u16 = 1;
+// Keys are serialized by TFHE-rs, whose encoding may change between minor versions.
+const TFHE_VERSION: &str = "0.6";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum KeyKind {
+    Client,
+    Server,
+    CompressedServer,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyHeader {
+    magic: [u8; 4],
+    version: u16,
+    tfhe_version: String,
+    kind: KeyKind,
+    profile: String,
+}
+
+#[derive(Debug)]
+pub(crate) enum KeyError {
+    Io(PathBuf, std::io::Error),
+    Encoding(PathBuf, bincode::Error),
+    NotAKeyFile(PathBuf),
+    Version {
+        path: PathBuf,
+        found: String,
+    },
+    Kind {
+        path: PathBuf,
+        expected: KeyKind,
+        found: KeyKind,
+    },
+    Profile {
+        path: PathBuf,
+        // None when the profile of the file is unknown to this build.
+        expected: Option<ParameterProfile>,
+        found: String,
+    },
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            KeyError::Io(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+            KeyError::Encoding(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+            KeyError::NotAKeyFile(ref path) => write!(f, "{}: not a key file", path.display()),
+            KeyError::Version {
+                ref path,
+                ref found,
+            } => write!(
+                f,
+                "{}: written by format {}, this build reads format {} with TFHE-rs {}",
+                path.display(),
+                found,
+                FORMAT_VERSION,
+                TFHE_VERSION
+            ),
+            KeyError::Kind {
+                ref path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}: holds a {:?} key, expected a {:?} key",
+                path.display(),
+                found,
+                expected
+            ),
+            KeyError::Profile {
+                ref path,
+                expected: Some(expected),
+                ref found,
+            } => write!(
+                f,
+                "{}: generated with the `{}` parameters, expected `{}`",
+                path.display(),
+                found,
+                expected
+            ),
+            KeyError::Profile {
+                ref path,
+                expected: None,
+                ref found,
+            } => write!(
+                f,
+                "{}: generated with unknown parameters `{}`",
+                path.display(),
+                found
+            ),
+        }
+    }
+}
+
+impl Error for KeyError {}
+
+/// Writes a key to `path`, after a header recording its kind and parameter profile.
+pub(crate) fn write_key<T: Serialize>(
+    path: &Path,
+    kind: KeyKind,
+    profile: ParameterProfile,
+    key: &T,
+) -> Result<(), KeyError> {
+    let header = KeyHeader {
+        magic: MAGIC,
+        version: FORMAT_VERSION,
+        tfhe_version: TFHE_VERSION.to_string(),
+        kind,
+        profile: profile.name().to_string(),
+    };
+    let file = create_key_file(path, kind).map_err(|err| KeyError::Io(path.to_path_buf(), err))?;
+    let mut writer = BufWriter::new(file);
+    bincode::serialize_into(&mut writer, &header)
+        .and_then(|_| bincode::serialize_into(&mut writer, key))
+        .map_err(|err| KeyError::Encoding(path.to_path_buf(), err))?;
+    writer
+        .flush()
+        .map_err(|err| KeyError::Io(path.to_path_buf(), err))
+}
+
+// The client key is secret, so its file is only readable by its owner, even if it existed before.
+#[cfg(unix)]
+fn create_key_file(path: &Path, kind: KeyKind) -> std::io::Result<File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    if kind != KeyKind::Client {
+        return File::create(path);
+    }
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_key_file(path: &Path, _kind: KeyKind) -> std::io::Result<File> {
+    File::create(path)
+}
+
+/// Reads a key written by `write_key`. The header is checked before the key is decoded, and the
+/// profile must be `expected` when one is given. Returns the key with its profile.
+pub(crate) fn read_key<T: DeserializeOwned>(
+    path: &Path,
+    kind: KeyKind,
+    expected: Option<ParameterProfile>,
+) -> Result<(T, ParameterProfile), KeyError> {
+    let file = File::open(path).map_err(|err| KeyError::Io(path.to_path_buf(), err))?;
+    let mut reader = BufReader::new(file);
+
+    let header: KeyHeader = bincode::deserialize_from(&mut reader)
+        .map_err(|_| KeyError::NotAKeyFile(path.to_path_buf()))?;
+    if header.magic != MAGIC {
+        return Err(KeyError::NotAKeyFile(path.to_path_buf()));
+    }
+    if header.version != FORMAT_VERSION || header.tfhe_version != TFHE_VERSION {
+        return Err(KeyError::Version {
+            path: path.to_path_buf(),
+            found: format!("{} with TFHE-rs {}", header.version, header.tfhe_version),
+        });
+    }
+    if header.kind != kind {
+        return Err(KeyError::Kind {
+            path: path.to_path_buf(),
+            expected: kind,
+            found: header.kind,
+        });
+    }
+    let profile = match ParameterProfile::from_name(&header.profile) {
+        Some(profile) if expected.is_none_or(|expected| expected == profile) => profile,
+        Some(_) => {
+            return Err(KeyError::Profile {
+                path: path.to_path_buf(),
+                expected,
+                found: header.profile,
+            })
+        }
+        None => {
+            return Err(KeyError::Profile {
+                path: path.to_path_buf(),
+                expected: None,
+                found: header.profile,
+            })
+        }
+    };
+
+    let key = bincode::deserialize_from(&mut reader)
+        .map_err(|err| KeyError::Encoding(path.to_path_buf(), err))?;
+    Ok((key, profile))
+}
+
+/// Writes the server key to `dir`, either as is or compressed.
+pub(crate) fn save_server_key(
+    dir: &Path,
+    profile: ParameterProfile,
+    server_key: &ServerKey,
+    compressed: Option<&CompressedServerKey>,
+) -> Result<(), KeyError> {
+    fs::create_dir_all(dir).map_err(|err| KeyError::Io(dir.to_path_buf(), err))?;
+    match compressed {
+        Some(compressed) => write_key(
+            &dir.join(COMPRESSED_SERVER_KEY_FILE),
+            KeyKind::CompressedServer,
+            profile,
+            compressed,
+        ),
+        None => write_key(
+            &dir.join(SERVER_KEY_FILE),
+            KeyKind::Server,
+            profile,
+            server_key,
+        ),
+    }
+}
+
+/// Loads the server key from `dir`, decompressing it if only the compressed key is there.
+pub(crate) fn load_server_key(
+    dir: &Path,
+    expected: Option<ParameterProfile>,
+) -> Result<(ServerKey, ParameterProfile), KeyError> {
+    let path = dir.join(SERVER_KEY_FILE);
+    let compressed_path = dir.join(COMPRESSED_SERVER_KEY_FILE);
+    if !path.exists() && compressed_path.exists() {
+        let (compressed, profile): (CompressedServerKey, _) =
+            read_key(&compressed_path, KeyKind::CompressedServer, expected)?;
+        return Ok((compressed.decompress(), profile));
+    }
+    read_key(&path, KeyKind::Server, expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fresh directory for the files of one test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("encrypt_sql-keys-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Writes a key file with the given header and a `u64` in place of the key.
+    fn write_header(path: &Path, header: &KeyHeader) {
+        let mut bytes = bincode::serialize(header).unwrap();
+        bytes.extend(bincode::serialize(&7u64).unwrap());
+        fs::write(path, bytes).unwrap();
+    }
+
+    fn header(version: u16, kind: KeyKind, profile: &str) -> KeyHeader {
+        KeyHeader {
+            magic: MAGIC,
+            version,
+            tfhe_version: TFHE_VERSION.to_string(),
+            kind,
+            profile: profile.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_back_a_key_with_its_profile() {
+        let path = scratch_dir("round-trip").join(CLIENT_KEY_FILE);
+        write_key(&path, KeyKind::Client, ParameterProfile::MultiBit, &7u64).unwrap();
+        let (key, profile): (u64, _) = read_key(&path, KeyKind::Client, None).unwrap();
+        assert_eq!((key, profile), (7, ParameterProfile::MultiBit));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn client_key_is_only_readable_by_its_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = scratch_dir("permissions").join(CLIENT_KEY_FILE);
+        // A key file left by an earlier run with wider permissions.
+        fs::write(&path, b"old key").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_key(&path, KeyKind::Client, ParameterProfile::Default, &7u64).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn rejects_other_files_and_versions() {
+        let dir = scratch_dir("headers");
+        let path = dir.join("key.bin");
+
+        fs::write(&path, b"not a key").unwrap();
+        assert!(matches!(
+            read_key::<u64>(&path, KeyKind::Client, None),
+            Err(KeyError::NotAKeyFile(_))
+        ));
+
+        write_header(
+            &path,
+            &header(FORMAT_VERSION + 1, KeyKind::Client, "default"),
+        );
+        assert!(matches!(
+            read_key::<u64>(&path, KeyKind::Client, None),
+            Err(KeyError::Version { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_the_wrong_kind_or_profile() {
+        let dir = scratch_dir("kinds");
+        let path = dir.join("key.bin");
+
+        write_header(&path, &header(FORMAT_VERSION, KeyKind::Server, "default"));
+        assert!(matches!(
+            read_key::<u64>(&path, KeyKind::Client, None),
+            Err(KeyError::Kind {
+                expected: KeyKind::Client,
+                found: KeyKind::Server,
+                ..
+            })
+        ));
+        assert!(matches!(
+            read_key::<u64>(&path, KeyKind::Server, Some(ParameterProfile::SmallMessage)),
+            Err(KeyError::Profile {
+                expected: Some(ParameterProfile::SmallMessage),
+                ..
+            })
+        ));
+
+        write_header(&path, &header(FORMAT_VERSION, KeyKind::Server, "unknown"));
+        assert!(matches!(
+            read_key::<u64>(&path, KeyKind::Server, None),
+            Err(KeyError::Profile { expected: None, .. })
+        ));
+    }
+}
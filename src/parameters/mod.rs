@@ -0,0 +1,81 @@
+//! Named TFHE parameter profiles. The profile decides the parameters of the shortint blocks
+//! every encrypted integer is made of, and is printed with the results so that benchmark numbers
+//! can be reproduced.
+
+use std::fmt;
+
+use tfhe::shortint::parameters::{
+    PARAM_MESSAGE_1_CARRY_1_KS_PBS, PARAM_MESSAGE_2_CARRY_2_KS_PBS,
+    PARAM_MULTI_BIT_MESSAGE_2_CARRY_2_GROUP_3_KS_PBS,
+};
+use tfhe::shortint::PBSParameters;
+use tfhe::{Config, ConfigBuilder};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ParameterProfile {
+    // Classic PBS with 2-bit messages and 2-bit carries, the TFHE-rs default.
+    #[default]
+    Default,
+    // Multi-bit PBS, which bootstraps several bits at once for more throughput on many cores.
+    MultiBit,
+    // 1-bit messages: more blocks per integer, but cheaper bootstraps for predicates that are
+    // mostly boolean logic.
+    SmallMessage,
+}
+
+/// Every profile, in the order they are listed on the command line.
+pub(crate) const PROFILES: [ParameterProfile; 3] = [
+    ParameterProfile::Default,
+    ParameterProfile::MultiBit,
+    ParameterProfile::SmallMessage,
+];
+
+impl ParameterProfile {
+    pub fn from_name(name: &str) -> Option<ParameterProfile> {
+        PROFILES.into_iter().find(|profile| profile.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ParameterProfile::Default => "default",
+            ParameterProfile::MultiBit => "multi-bit",
+            ParameterProfile::SmallMessage => "small-message",
+        }
+    }
+
+    pub fn parameters(self) -> PBSParameters {
+        match self {
+            ParameterProfile::Default => default_cpu_parameters(),
+            ParameterProfile::MultiBit => PARAM_MULTI_BIT_MESSAGE_2_CARRY_2_GROUP_3_KS_PBS.into(),
+            ParameterProfile::SmallMessage => PARAM_MESSAGE_1_CARRY_1_KS_PBS.into(),
+        }
+    }
+
+    /// Stable fingerprint of the parameters, written in encrypted files so that ciphertexts are
+    /// never read with other parameters than they were made with. It is the FNV-1a hash of the
+    /// parameters' Debug rendering, which lists every field.
+    pub fn fingerprint(self) -> u64 {
+        format!("{:?}", self.parameters())
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    pub fn config(self) -> Config {
+        ConfigBuilder::default()
+            .use_custom_parameters(self.parameters(), None)
+            .build()
+    }
+}
+
+impl fmt::Display for ParameterProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The parameters `ConfigBuilder::default()` uses on CPU.
+pub(crate) fn default_cpu_parameters() -> PBSParameters {
+    PARAM_MESSAGE_2_CARRY_2_KS_PBS.into()
+}
@@ -0,0 +1,447 @@
+//! Types shared by the client and the server: the encrypted query IR and the encrypted result.
+//! Nothing in this module has access to the client key.
+
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlparser::ast::BinaryOperator;
+use sqlparser::parser::ParserError;
+use tfhe::{
+    FheBool, FheInt16, FheInt32, FheInt64, FheInt8, FheUint16, FheUint32, FheUint64, FheUint8,
+};
+
+use crate::schema::IntegerType;
+
+#[derive(Debug)]
+pub(crate) enum QueryError {
+    Io(std::io::Error),
+    Parse(ParserError),
+    Unsupported(String),
+    UnknownTable(String),
+    UnknownColumn(String),
+    Malformed(String),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            QueryError::Io(ref err) => write!(f, "IO error: {}", err),
+            QueryError::Parse(ref err) => write!(f, "SQL parse error: {}", err),
+            QueryError::Unsupported(ref what) => write!(f, "Unsupported SQL: {}", what),
+            QueryError::UnknownTable(ref name) => write!(f, "Unknown table: {}", name),
+            QueryError::UnknownColumn(ref name) => write!(f, "Unknown column: {}", name),
+            QueryError::Malformed(ref what) => write!(f, "Malformed encrypted query: {}", what),
+        }
+    }
+}
+
+impl Error for QueryError {}
+
+impl From<std::io::Error> for QueryError {
+    fn from(err: std::io::Error) -> QueryError {
+        QueryError::Io(err)
+    }
+}
+
+impl From<ParserError> for QueryError {
+    fn from(err: ParserError) -> QueryError {
+        QueryError::Parse(err)
+    }
+}
+
+/// A SQL query compiled into a tree of encrypted nodes.
+///
+/// The shape of the query (number of projected items, the predicate tree) is kept in clear so
+/// that the server knows what to evaluate, while the queried table, the referenced columns and
+/// every literal are encrypted with the client key.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EncryptedQuery {
+    pub table: TableSelector,
+    // Encrypted column positions, referenced by slot from the projection and the predicate.
+    pub columns: Vec<FheUint8>,
+    pub projection: Vec<Projection>,
+    // SELECT DISTINCT: rows whose projected cells repeat an earlier selected row are dropped.
+    pub distinct: bool,
+    pub selection: Option<Predicate>,
+    // Number of rows of the result. Defaults to, and is capped at, the row count of the largest
+    // scanned table, so that every matching row fits.
+    pub max_rows: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum TableSelector {
+    // Encrypted position of the table in the schema: the server scans every table.
+    Encrypted(FheUint8),
+    // Table name in clear: only that table is scanned, which reveals it to the server.
+    Clear(String),
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Projection {
+    // Every column of the table, in schema order.
+    Wildcard,
+    // Slot of the encrypted column position in `EncryptedQuery::columns`.
+    Column(usize),
+    // String concatenation, e.g. `first || ' ' || last`, computed by the server.
+    Concat(Vec<ConcatPart>),
+    // Integer arithmetic, e.g. `id % 7`, computed by the server.
+    Arithmetic(Arithmetic),
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum ConcatPart {
+    // Slot of the encrypted position of a string column.
+    Column(usize),
+    // Encrypted bytes, zero-padded like a string literal of a predicate.
+    Literal(Vec<FheUint8>),
+}
+
+/// Deepest nesting of predicates and arithmetic expressions in a query. The server evaluates
+/// them recursively, so a query nested deeper is rejected while it is decoded, before it can
+/// overflow the stack of a session.
+pub(crate) const MAX_DEPTH: usize = 64;
+
+thread_local! {
+    // Nesting of the predicates and expressions being decoded on this thread.
+    static DECODING_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+// Decodes a nested predicate or expression, failing beyond `MAX_DEPTH`.
+fn nested<'de, D, T>(deserializer: D) -> Result<Box<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let depth = DECODING_DEPTH.with(|depth| depth.replace(depth.get() + 1)) + 1;
+    let nested = if depth > MAX_DEPTH {
+        Err(D::Error::custom("query nested too deeply"))
+    } else {
+        Box::<T>::deserialize(deserializer)
+    };
+    DECODING_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    nested
+}
+
+/// Predicate tree built from a WHERE clause.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Predicate {
+    And(
+        #[serde(deserialize_with = "nested")] Box<Predicate>,
+        #[serde(deserialize_with = "nested")] Box<Predicate>,
+    ),
+    Or(
+        #[serde(deserialize_with = "nested")] Box<Predicate>,
+        #[serde(deserialize_with = "nested")] Box<Predicate>,
+    ),
+    Not(#[serde(deserialize_with = "nested")] Box<Predicate>),
+    Compare {
+        left: Operand,
+        op: ComparisonOp,
+        right: Operand,
+    },
+    InList {
+        expr: Operand,
+        list: Vec<EncryptedLiteral>,
+        negated: bool,
+    },
+    Between {
+        expr: Operand,
+        low: Operand,
+        high: Operand,
+        negated: bool,
+    },
+    // A bool column used as a predicate, e.g. `WHERE flag`.
+    Column(ColumnRef),
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Operand {
+    Column(ColumnRef),
+    Literal(EncryptedLiteral),
+    Arithmetic(#[serde(deserialize_with = "nested")] Box<Arithmetic>),
+}
+
+/// An arithmetic operation on integers. The result wraps around at `integer_type`, the type of
+/// the columns it computes on, which is sent in clear. Its literals are encrypted with that type.
+/// SQLite computes in int64 instead, so an overflow makes the result differ from the clear one.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Arithmetic {
+    pub left: Operand,
+    pub op: ArithmeticOp,
+    pub right: Operand,
+    pub integer_type: IntegerType,
+}
+
+/// Arithmetic operators. Division and modulo truncate toward zero, like SQLite, and raise an
+/// encrypted error flag when dividing by zero, where SQLite returns NULL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum ColumnRef {
+    // Column name in clear, used when the table itself is named in clear.
+    Name(String),
+    // Slot of the encrypted column position in `EncryptedQuery::columns`.
+    Slot(usize),
+}
+
+/// Encrypted strings are zero-padded to this many bytes unless the client is configured
+/// otherwise, so that their length stays hidden.
+pub(crate) const DEFAULT_STRING_LENGTH: usize = 32;
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum EncryptedLiteral {
+    Integer(EncryptedInteger),
+    Boolean(FheBool),
+    // Encrypted bytes, zero-padded to the string length of the client.
+    String(Vec<FheUint8>),
+}
+
+/// An encrypted integer literal, stored at the width of the column it is compared with, or a wider
+/// one when the column cannot hold it. The variant is sent in clear, so it reveals that width and
+/// signedness.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum EncryptedInteger {
+    U8(FheUint8),
+    U16(FheUint16),
+    U32(FheUint32),
+    U64(FheUint64),
+    I8(FheInt8),
+    I16(FheInt16),
+    I32(FheInt32),
+    I64(FheInt64),
+}
+
+/// Comparison operators, each with its own tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum ComparisonOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl ComparisonOp {
+    pub(crate) fn from_binary_operator(op: &BinaryOperator) -> Option<ComparisonOp> {
+        match op {
+            BinaryOperator::Eq => Some(ComparisonOp::Eq),
+            BinaryOperator::NotEq => Some(ComparisonOp::NotEq),
+            BinaryOperator::Lt => Some(ComparisonOp::Lt),
+            BinaryOperator::LtEq => Some(ComparisonOp::LtEq),
+            BinaryOperator::Gt => Some(ComparisonOp::Gt),
+            BinaryOperator::GtEq => Some(ComparisonOp::GtEq),
+            _ => None,
+        }
+    }
+
+    /// Returns the operator to use once the operands are swapped, e.g. `a < b` is `b > a`.
+    pub fn flip(self) -> ComparisonOp {
+        match self {
+            ComparisonOp::Eq => ComparisonOp::Eq,
+            ComparisonOp::NotEq => ComparisonOp::NotEq,
+            ComparisonOp::Lt => ComparisonOp::Gt,
+            ComparisonOp::LtEq => ComparisonOp::GtEq,
+            ComparisonOp::Gt => ComparisonOp::Lt,
+            ComparisonOp::GtEq => ComparisonOp::LtEq,
+        }
+    }
+
+    /// Whether the comparison holds for two cleartext values that compare as `ordering`.
+    pub fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            ComparisonOp::Eq => ordering == Ordering::Equal,
+            ComparisonOp::NotEq => ordering != Ordering::Equal,
+            ComparisonOp::Lt => ordering == Ordering::Less,
+            ComparisonOp::LtEq => ordering != Ordering::Greater,
+            ComparisonOp::Gt => ordering == Ordering::Greater,
+            ComparisonOp::GtEq => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl ArithmeticOp {
+    pub(crate) fn from_binary_operator(op: &BinaryOperator) -> Option<ArithmeticOp> {
+        match op {
+            BinaryOperator::Plus => Some(ArithmeticOp::Add),
+            BinaryOperator::Minus => Some(ArithmeticOp::Subtract),
+            BinaryOperator::Multiply => Some(ArithmeticOp::Multiply),
+            BinaryOperator::Divide => Some(ArithmeticOp::Divide),
+            BinaryOperator::Modulo => Some(ArithmeticOp::Modulo),
+            _ => None,
+        }
+    }
+}
+
+impl EncryptedQuery {
+    /// Number of encrypted literals carried by the query.
+    pub fn literal_count(&self) -> usize {
+        let projected: usize = self
+            .projection
+            .iter()
+            .map(|item| match item {
+                Projection::Concat(parts) => parts
+                    .iter()
+                    .filter(|part| matches!(part, ConcatPart::Literal(_)))
+                    .count(),
+                Projection::Arithmetic(arithmetic) => arithmetic.literal_count(),
+                Projection::Wildcard | Projection::Column(_) => 0,
+            })
+            .sum();
+        projected + self.selection.as_ref().map_or(0, Predicate::literal_count)
+    }
+
+    /// Deepest nesting of its predicates and arithmetic expressions.
+    pub fn depth(&self) -> usize {
+        let projected = self.projection.iter().map(|item| match item {
+            Projection::Arithmetic(arithmetic) => arithmetic.depth(),
+            Projection::Wildcard | Projection::Column(_) | Projection::Concat(_) => 0,
+        });
+        let selection = self.selection.as_ref().map_or(0, Predicate::depth);
+        projected.fold(selection, usize::max)
+    }
+}
+
+impl Predicate {
+    fn literal_count(&self) -> usize {
+        match self {
+            Predicate::And(left, right) | Predicate::Or(left, right) => {
+                left.literal_count() + right.literal_count()
+            }
+            Predicate::Not(inner) => inner.literal_count(),
+            Predicate::Compare { left, right, .. } => left.literal_count() + right.literal_count(),
+            Predicate::InList { expr, list, .. } => expr.literal_count() + list.len(),
+            Predicate::Between {
+                expr, low, high, ..
+            } => expr.literal_count() + low.literal_count() + high.literal_count(),
+            Predicate::Column(_) => 0,
+        }
+    }
+
+    fn depth(&self) -> usize {
+        1 + match self {
+            Predicate::And(left, right) | Predicate::Or(left, right) => {
+                left.depth().max(right.depth())
+            }
+            Predicate::Not(inner) => inner.depth(),
+            Predicate::Compare { left, right, .. } => left.depth().max(right.depth()),
+            Predicate::InList { expr, .. } => expr.depth(),
+            Predicate::Between {
+                expr, low, high, ..
+            } => expr.depth().max(low.depth()).max(high.depth()),
+            Predicate::Column(_) => 0,
+        }
+    }
+}
+
+impl Operand {
+    fn literal_count(&self) -> usize {
+        match self {
+            Operand::Column(_) => 0,
+            Operand::Literal(_) => 1,
+            Operand::Arithmetic(arithmetic) => arithmetic.literal_count(),
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            Operand::Column(_) | Operand::Literal(_) => 0,
+            Operand::Arithmetic(arithmetic) => arithmetic.depth(),
+        }
+    }
+}
+
+impl Arithmetic {
+    fn literal_count(&self) -> usize {
+        self.left.literal_count() + self.right.literal_count()
+    }
+
+    fn depth(&self) -> usize {
+        1 + self.left.depth().max(self.right.depth())
+    }
+
+    /// Whether the expression divides, and so may divide by zero.
+    pub fn divides(&self) -> bool {
+        let divides = |operand: &Operand| match operand {
+            Operand::Arithmetic(arithmetic) => arithmetic.divides(),
+            Operand::Column(_) | Operand::Literal(_) => false,
+        };
+        matches!(self.op, ArithmeticOp::Divide | ArithmeticOp::Modulo)
+            || divides(&self.left)
+            || divides(&self.right)
+    }
+}
+
+impl fmt::Display for EncryptedQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Encrypted SQL Query ({} projected item(s), {} encrypted literal(s))",
+            self.projection.len(),
+            self.literal_count()
+        )
+    }
+}
+
+/// Output of `run_fhe_query`: a fixed number of rows, the selected rows first in table order,
+/// followed by empty rows. The number of rows depends on the tables and `max_rows` only, so the
+/// result does not reveal how many rows matched, nor which ones.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EncryptedResult {
+    pub rows: Vec<EncryptedRow>,
+    // Number of selected rows. It is larger than the number of rows when they did not all fit.
+    pub count: FheUint32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct EncryptedRow {
+    pub selected: FheBool,
+    pub cells: Vec<EncryptedCell>,
+}
+
+/// A projected value, as its bytes zero-padded to the widest value of the table so that every
+/// cell of a result has the same size whichever column it was selected from.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct EncryptedCell {
+    pub bytes: Vec<FheUint8>,
+    // Length of a concatenated string. Its capacity is the sum of the capacities of its parts.
+    pub length: Option<FheUint16>,
+    // Set when computing an arithmetic value divided by zero: the value is NULL.
+    pub error: Option<FheBool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negations(depth: usize) -> Predicate {
+        (0..depth).fold(Predicate::Column(ColumnRef::Slot(0)), |inner, _| {
+            Predicate::Not(Box::new(inner))
+        })
+    }
+
+    #[test]
+    fn rejects_predicates_nested_too_deeply_while_decoding() {
+        let bytes = bincode::serialize(&negations(MAX_DEPTH)).unwrap();
+        let predicate: Predicate = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(predicate.depth(), MAX_DEPTH + 1);
+
+        let bytes = bincode::serialize(&negations(MAX_DEPTH + 1)).unwrap();
+        assert!(bincode::deserialize::<Predicate>(&bytes).is_err());
+        // A failed decoding leaves the depth count of the thread as it was.
+        assert!(bincode::deserialize::<Predicate>(&bytes).is_err());
+        let bytes = bincode::serialize(&negations(1)).unwrap();
+        assert!(bincode::deserialize::<Predicate>(&bytes).is_ok());
+    }
+}
@@ -0,0 +1,130 @@
+//! Query results in clear, as decrypted by the client or returned by SQLite, and the comparison
+//! between the two.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::schema::Row;
+
+/// Rows of a query result, each holding the projected values with their column types.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ResultSet {
+    pub rows: Vec<Row>,
+}
+
+/// Rows found in only one of two result sets.
+#[derive(Debug, Default)]
+pub(crate) struct ResultDiff {
+    // Rows of the clear result missing from the encrypted one.
+    pub missing: Vec<Row>,
+    // Rows of the encrypted result that the clear one does not have.
+    pub unexpected: Vec<Row>,
+}
+
+impl ResultSet {
+    /// Compares the rows of two results as multisets. Values are compared with their types, so
+    /// `1` and `'1'` differ. Row order is ignored: without ORDER BY, SQL leaves it unspecified
+    /// and the encrypted result keeps table order.
+    pub fn diff(&self, encrypted: &ResultSet) -> ResultDiff {
+        let mut clear = self.rows.clone();
+        let mut encrypted = encrypted.rows.clone();
+        clear.sort();
+        encrypted.sort();
+
+        let mut diff = ResultDiff::default();
+        let mut clear = clear.into_iter().peekable();
+        let mut encrypted = encrypted.into_iter().peekable();
+        loop {
+            let ordering = match (clear.peek(), encrypted.peek()) {
+                (Some(left), Some(right)) => left.cmp(right),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            match ordering {
+                Ordering::Less => diff.missing.extend(clear.next()),
+                Ordering::Greater => diff.unexpected.extend(encrypted.next()),
+                Ordering::Equal => {
+                    clear.next();
+                    encrypted.next();
+                }
+            }
+        }
+        diff
+    }
+}
+
+impl ResultDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+impl fmt::Display for ResultSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows: Vec<String> = self.rows.iter().map(format_row).collect();
+        write!(f, "{}", rows.join(" "))
+    }
+}
+
+// One line per mismatched row: `-` for rows only in the clear result, `+` for rows only in the
+// encrypted one.
+impl fmt::Display for ResultDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in &self.missing {
+            writeln!(f, "- {}", format_row(row))?;
+        }
+        for row in &self.unexpected {
+            writeln!(f, "+ {}", format_row(row))?;
+        }
+        Ok(())
+    }
+}
+
+fn format_row(row: &Row) -> String {
+    let values: Vec<String> = row.values.iter().map(ToString::to_string).collect();
+    format!("({})", values.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Value;
+
+    fn result(rows: &[&[Value]]) -> ResultSet {
+        ResultSet {
+            rows: rows
+                .iter()
+                .map(|values| Row {
+                    values: values.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn ignores_row_order() {
+        let clear = result(&[&[Value::Integer(1)], &[Value::Integer(2)]]);
+        let encrypted = result(&[&[Value::Integer(2)], &[Value::Integer(1)]]);
+        assert!(clear.diff(&encrypted).is_empty());
+    }
+
+    #[test]
+    fn counts_repeated_rows() {
+        let clear = result(&[&[Value::Integer(1)], &[Value::Integer(1)]]);
+        let encrypted = result(&[&[Value::Integer(1)]]);
+        let diff = clear.diff(&encrypted);
+        assert_eq!(diff.missing, result(&[&[Value::Integer(1)]]).rows);
+        assert!(diff.unexpected.is_empty());
+    }
+
+    #[test]
+    fn compares_values_with_their_types() {
+        let clear = result(&[&[Value::Integer(1), Value::Null]]);
+        let encrypted = result(&[&[Value::String("1".to_string()), Value::Null]]);
+        let diff = clear.diff(&encrypted);
+        assert_eq!(diff.missing, clear.rows);
+        assert_eq!(diff.unexpected, encrypted.rows);
+        assert_eq!(diff.to_string(), "- (1, NULL)\n+ (1, NULL)\n");
+    }
+}
@@ -0,0 +1,633 @@
+//! Public description of the tables held by the server. The schema is not secret: the client
+//! uses it to turn table and column names into encrypted positions, and to encrypt literals with
+//! the type of the column they are compared with.
+
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Most columns a table may have. Column positions are encrypted as `FheUint8`.
+pub(crate) const MAX_COLUMNS: usize = 256;
+/// Most tables a database may have. Table positions are encrypted as `FheUint8`.
+pub(crate) const MAX_TABLES: usize = 256;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Schema {
+    pub tables: Vec<TableSchema>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TableSchema {
+    pub name: String,
+    // Columns in the order of the CSV header.
+    pub columns: Vec<Column>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Column {
+    pub name: String,
+    pub data_type: DataType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum DataType {
+    Integer(IntegerType),
+    Boolean,
+    String,
+}
+
+/// A CSV record parsed with the types of its table's columns, in column order.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Row {
+    pub values: Vec<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Value {
+    // A division by zero.
+    Null,
+    // Every integer type fits, since `uint64` columns are capped to the SQLite range.
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum IntegerType {
+    Signed8,
+    Unsigned8,
+    Signed16,
+    Unsigned16,
+    Signed32,
+    Unsigned32,
+    Signed64,
+    Unsigned64,
+}
+
+/// A CSV file that does not match the `name:type` header format or its declared types.
+#[derive(Debug)]
+pub(crate) enum SchemaError {
+    // The file name is not a valid table name.
+    TableName(PathBuf),
+    // A column name is empty or holds control characters.
+    ColumnName {
+        file: PathBuf,
+        column: String,
+    },
+    MissingType {
+        file: PathBuf,
+        column: String,
+    },
+    UnknownType {
+        file: PathBuf,
+        column: String,
+        tag: String,
+    },
+    DuplicateColumn {
+        file: PathBuf,
+        column: String,
+    },
+    TooManyColumns {
+        file: PathBuf,
+        count: usize,
+    },
+    FieldCount {
+        file: PathBuf,
+        line: u64,
+        expected: usize,
+        found: usize,
+    },
+    InvalidValue {
+        file: PathBuf,
+        line: u64,
+        column: String,
+        data_type: DataType,
+        value: String,
+    },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SchemaError::TableName(ref file) => {
+                write!(f, "{}: file name is not a valid table name", file.display())
+            }
+            SchemaError::ColumnName {
+                ref file,
+                ref column,
+            } => write!(
+                f,
+                "{}: `{}` is not a valid column name",
+                file.display(),
+                column.escape_debug()
+            ),
+            SchemaError::MissingType {
+                ref file,
+                ref column,
+            } => write!(
+                f,
+                "{}: column `{}` has no type, expected `{}:<type>`",
+                file.display(),
+                column,
+                column
+            ),
+            SchemaError::UnknownType {
+                ref file,
+                ref column,
+                ref tag,
+            } => write!(
+                f,
+                "{}: column `{}` has unknown type `{}`, expected one of {}",
+                file.display(),
+                column,
+                tag,
+                TYPE_TAGS.join(", ")
+            ),
+            SchemaError::DuplicateColumn {
+                ref file,
+                ref column,
+            } => write!(
+                f,
+                "{}: column `{}` is declared twice",
+                file.display(),
+                column
+            ),
+            SchemaError::TooManyColumns { ref file, count } => write!(
+                f,
+                "{}: {} columns, at most {} are supported",
+                file.display(),
+                count,
+                MAX_COLUMNS
+            ),
+            SchemaError::FieldCount {
+                ref file,
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}:{}: expected {} field(s), found {}",
+                file.display(),
+                line,
+                expected,
+                found
+            ),
+            SchemaError::InvalidValue {
+                ref file,
+                line,
+                ref column,
+                data_type,
+                ref value,
+            } => write!(
+                f,
+                "{}:{}: value `{}` of column `{}` is not a valid {}",
+                file.display(),
+                line,
+                value,
+                column,
+                data_type
+            ),
+        }
+    }
+}
+
+impl Error for SchemaError {}
+
+// Type tags accepted in CSV headers.
+const TYPE_TAGS: [&str; 10] = [
+    "int8", "uint8", "int16", "uint16", "int32", "uint32", "int64", "uint64", "bool", "string",
+];
+
+impl Schema {
+    pub fn table(&self, name: &str) -> Option<&TableSchema> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+
+    // Position of a table, which is what the client encrypts to hide the table it queries.
+    pub fn table_position(&self, name: &str) -> Option<usize> {
+        self.tables.iter().position(|table| table.name == name)
+    }
+
+    // Column count of the widest table.
+    pub fn max_columns(&self) -> usize {
+        self.tables
+            .iter()
+            .map(|table| table.columns.len())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl TableSchema {
+    /// Builds the schema of the table stored in a CSV file from its `name:type` headers. The
+    /// table is named after the file.
+    pub fn from_headers<'h>(
+        file: &Path,
+        headers: impl IntoIterator<Item = &'h str>,
+    ) -> Result<TableSchema, SchemaError> {
+        let name = file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .filter(|stem| valid_name(stem))
+            .ok_or_else(|| SchemaError::TableName(file.to_path_buf()))?
+            .to_string();
+
+        let mut columns: Vec<Column> = Vec::new();
+        for header in headers {
+            let column = Column::from_header(file, header)?;
+            if columns.iter().any(|other| other.name == column.name) {
+                return Err(SchemaError::DuplicateColumn {
+                    file: file.to_path_buf(),
+                    column: column.name,
+                });
+            }
+            columns.push(column);
+        }
+        if columns.len() > MAX_COLUMNS {
+            return Err(SchemaError::TooManyColumns {
+                file: file.to_path_buf(),
+                count: columns.len(),
+            });
+        }
+        Ok(TableSchema { name, columns })
+    }
+
+    /// Parses the fields of a CSV record, found at `line` of `file`, into a typed row.
+    pub fn parse_row(&self, file: &Path, line: u64, fields: &[&str]) -> Result<Row, SchemaError> {
+        if fields.len() != self.columns.len() {
+            return Err(SchemaError::FieldCount {
+                file: file.to_path_buf(),
+                line,
+                expected: self.columns.len(),
+                found: fields.len(),
+            });
+        }
+        let values = self
+            .columns
+            .iter()
+            .zip(fields)
+            .map(|(column, &field)| {
+                Value::parse(field, column.data_type).ok_or_else(|| SchemaError::InvalidValue {
+                    file: file.to_path_buf(),
+                    line,
+                    column: column.name.clone(),
+                    data_type: column.data_type,
+                    value: field.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Row { values })
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name == name)
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+}
+
+impl Column {
+    // Parses a `name:type` CSV header of `file`.
+    pub fn from_header(file: &Path, header: &str) -> Result<Column, SchemaError> {
+        let (name, tag) = match header.split_once(':') {
+            Some((name, tag)) => (name.trim(), tag.trim()),
+            None => {
+                return Err(SchemaError::MissingType {
+                    file: file.to_path_buf(),
+                    column: header.trim().to_string(),
+                })
+            }
+        };
+        if !valid_name(name) {
+            return Err(SchemaError::ColumnName {
+                file: file.to_path_buf(),
+                column: name.to_string(),
+            });
+        }
+        let data_type = DataType::from_tag(tag).ok_or_else(|| SchemaError::UnknownType {
+            file: file.to_path_buf(),
+            column: name.to_string(),
+            tag: tag.to_string(),
+        })?;
+        Ok(Column {
+            name: name.to_string(),
+            data_type,
+        })
+    }
+}
+
+// Table and column names are quoted in SQL, so any name is safe as long as it is not empty and
+// holds no control character.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(char::is_control)
+}
+
+impl Value {
+    // Parses a CSV field as a value of the given type, if it is one.
+    pub fn parse(field: &str, data_type: DataType) -> Option<Value> {
+        match data_type {
+            DataType::Integer(integer_type) => {
+                let value = field.trim().parse::<i128>().ok()?;
+                if value < integer_type.min_value() || value > integer_type.max_value() {
+                    return None;
+                }
+                Some(Value::Integer(value as i64))
+            }
+            DataType::Boolean => match field.trim() {
+                "true" => Some(Value::Boolean(true)),
+                "false" => Some(Value::Boolean(false)),
+                _ => None,
+            },
+            DataType::String => Some(Value::String(field.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Value::Null => write!(f, "NULL"),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::String(ref value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl DataType {
+    pub fn from_tag(tag: &str) -> Option<DataType> {
+        match tag {
+            "int8" => Some(DataType::Integer(IntegerType::Signed8)),
+            "uint8" => Some(DataType::Integer(IntegerType::Unsigned8)),
+            "int16" => Some(DataType::Integer(IntegerType::Signed16)),
+            "uint16" => Some(DataType::Integer(IntegerType::Unsigned16)),
+            "int32" => Some(DataType::Integer(IntegerType::Signed32)),
+            "uint32" => Some(DataType::Integer(IntegerType::Unsigned32)),
+            "int64" => Some(DataType::Integer(IntegerType::Signed64)),
+            "uint64" => Some(DataType::Integer(IntegerType::Unsigned64)),
+            "bool" => Some(DataType::Boolean),
+            "string" => Some(DataType::String),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DataType::Integer(integer_type) => write!(f, "{}", integer_type),
+            DataType::Boolean => write!(f, "bool"),
+            DataType::String => write!(f, "string"),
+        }
+    }
+}
+
+impl IntegerType {
+    pub fn bits(self) -> u32 {
+        match self {
+            IntegerType::Signed8 | IntegerType::Unsigned8 => 8,
+            IntegerType::Signed16 | IntegerType::Unsigned16 => 16,
+            IntegerType::Signed32 | IntegerType::Unsigned32 => 32,
+            IntegerType::Signed64 | IntegerType::Unsigned64 => 64,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(
+            self,
+            IntegerType::Signed8
+                | IntegerType::Signed16
+                | IntegerType::Signed32
+                | IntegerType::Signed64
+        )
+    }
+
+    // Smallest value a column of this type can hold.
+    pub fn min_value(self) -> i128 {
+        if self.is_signed() {
+            -(1i128 << (self.bits() - 1))
+        } else {
+            0
+        }
+    }
+
+    // Largest value a column of this type can hold. SQLite stores integers as i64, which also
+    // caps `uint64` columns.
+    pub fn max_value(self) -> i128 {
+        if self.is_signed() || self.bits() == 64 {
+            (1i128 << (self.bits() - 1)) - 1
+        } else {
+            (1i128 << self.bits()) - 1
+        }
+    }
+
+    pub fn holds(self, value: i128) -> bool {
+        (self.min_value()..=self.max_value()).contains(&value)
+    }
+
+    // The narrowest type holding `value`, unsigned unless it is negative.
+    pub fn narrowest(value: i128) -> Option<IntegerType> {
+        [8, 16, 32, 64]
+            .into_iter()
+            .map(|bits| IntegerType::from_parts(value < 0, bits))
+            .find(|integer_type| integer_type.holds(value))
+    }
+
+    // Type of an arithmetic result on values of two types: the wider type, signed if either is.
+    // An unsigned type is widened when combined with a signed one, so that its values still fit.
+    pub fn promote(self, other: IntegerType) -> IntegerType {
+        let bits = |integer_type: IntegerType| {
+            if integer_type.is_signed() || self.is_signed() == other.is_signed() {
+                integer_type.bits()
+            } else {
+                (integer_type.bits() * 2).min(64)
+            }
+        };
+        IntegerType::from_parts(
+            self.is_signed() || other.is_signed(),
+            bits(self).max(bits(other)),
+        )
+    }
+
+    fn from_parts(signed: bool, bits: u32) -> IntegerType {
+        match (signed, bits) {
+            (true, 8) => IntegerType::Signed8,
+            (true, 16) => IntegerType::Signed16,
+            (true, 32) => IntegerType::Signed32,
+            (true, _) => IntegerType::Signed64,
+            (false, 8) => IntegerType::Unsigned8,
+            (false, 16) => IntegerType::Unsigned16,
+            (false, 32) => IntegerType::Unsigned32,
+            (false, _) => IntegerType::Unsigned64,
+        }
+    }
+
+    /// Wraps a value around to this type, keeping its low bits, as fixed-width arithmetic does.
+    /// `uint64` values are kept in the int64 range of SQLite, so they wrap like int64.
+    pub fn wrap(self, value: i64) -> i64 {
+        let shift = 64 - self.bits();
+        if self.is_signed() || self.bits() == 64 {
+            (value << shift) >> shift
+        } else {
+            (((value as u64) << shift) >> shift) as i64
+        }
+    }
+}
+
+impl fmt::Display for IntegerType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.is_signed() { "int" } else { "uint" };
+        write!(f, "{}{}", sign, self.bits())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(headers: &[&str]) -> Result<TableSchema, SchemaError> {
+        TableSchema::from_headers(Path::new("db/people.csv"), headers.iter().copied())
+    }
+
+    #[test]
+    fn parses_typed_headers() {
+        let table =
+            schema(&["id:uint32", " name : string ", "balance:int64", "flag:bool"]).unwrap();
+        assert_eq!(table.name, "people");
+        let columns: Vec<(&str, DataType)> = table
+            .columns
+            .iter()
+            .map(|column| (column.name.as_str(), column.data_type))
+            .collect();
+        assert_eq!(
+            columns,
+            [
+                ("id", DataType::Integer(IntegerType::Unsigned32)),
+                ("name", DataType::String),
+                ("balance", DataType::Integer(IntegerType::Signed64)),
+                ("flag", DataType::Boolean),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert!(matches!(
+            schema(&["id"]),
+            Err(SchemaError::MissingType { .. })
+        ));
+        assert!(matches!(
+            schema(&["id:float"]),
+            Err(SchemaError::UnknownType { .. })
+        ));
+        assert!(matches!(
+            schema(&[":uint8"]),
+            Err(SchemaError::ColumnName { .. })
+        ));
+        assert!(matches!(
+            schema(&["id:uint8", "id:string"]),
+            Err(SchemaError::DuplicateColumn { .. })
+        ));
+        let headers: Vec<String> = (0..=MAX_COLUMNS).map(|i| format!("c{}:uint8", i)).collect();
+        let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+        assert!(schema(&headers[..MAX_COLUMNS]).is_ok());
+        assert!(matches!(
+            schema(&headers),
+            Err(SchemaError::TooManyColumns { count: 257, .. })
+        ));
+        assert!(matches!(
+            TableSchema::from_headers(Path::new("db/bad\nname.csv"), ["id:uint8"]),
+            Err(SchemaError::TableName(_))
+        ));
+    }
+
+    #[test]
+    fn parses_values_within_the_column_range() {
+        let uint8 = DataType::Integer(IntegerType::Unsigned8);
+        let int8 = DataType::Integer(IntegerType::Signed8);
+        let uint64 = DataType::Integer(IntegerType::Unsigned64);
+        assert_eq!(Value::parse(" 255 ", uint8), Some(Value::Integer(255)));
+        assert_eq!(Value::parse("256", uint8), None);
+        assert_eq!(Value::parse("-1", uint8), None);
+        assert_eq!(Value::parse("-128", int8), Some(Value::Integer(-128)));
+        assert_eq!(Value::parse("-129", int8), None);
+        assert_eq!(
+            Value::parse("9223372036854775807", uint64),
+            Some(Value::Integer(i64::MAX))
+        );
+        assert_eq!(Value::parse("9223372036854775808", uint64), None);
+        assert_eq!(Value::parse("abc", uint8), None);
+        assert_eq!(
+            Value::parse("true", DataType::Boolean),
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(Value::parse("1", DataType::Boolean), None);
+        assert_eq!(
+            Value::parse(" a, b ", DataType::String),
+            Some(Value::String(" a, b ".to_string()))
+        );
+    }
+
+    #[test]
+    fn parses_rows_against_the_schema() {
+        let table = schema(&["id:uint8", "name:string"]).unwrap();
+        let file = Path::new("db/people.csv");
+        assert_eq!(
+            table.parse_row(file, 2, &["7", "Ada"]).unwrap(),
+            Row {
+                values: vec![Value::Integer(7), Value::String("Ada".to_string())],
+            }
+        );
+        assert!(matches!(
+            table.parse_row(file, 3, &["7"]),
+            Err(SchemaError::FieldCount {
+                line: 3,
+                expected: 2,
+                found: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            table.parse_row(file, 4, &["300", "Ada"]),
+            Err(SchemaError::InvalidValue { line: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn promotes_to_a_type_holding_both_operands() {
+        use IntegerType::*;
+        assert_eq!(Unsigned8.promote(Unsigned32), Unsigned32);
+        assert_eq!(Signed32.promote(Signed8), Signed32);
+        assert_eq!(Unsigned8.promote(Signed8), Signed16);
+        assert_eq!(Signed8.promote(Unsigned16), Signed32);
+        assert_eq!(Unsigned32.promote(Signed8), Signed64);
+        assert_eq!(Signed16.promote(Unsigned64), Signed64);
+    }
+
+    #[test]
+    fn finds_the_narrowest_type_holding_a_value() {
+        use IntegerType::*;
+        assert_eq!(IntegerType::narrowest(255), Some(Unsigned8));
+        assert_eq!(IntegerType::narrowest(300), Some(Unsigned16));
+        assert_eq!(IntegerType::narrowest(-1), Some(Signed8));
+        assert_eq!(IntegerType::narrowest(-129), Some(Signed16));
+        assert_eq!(IntegerType::narrowest(1 << 40), Some(Unsigned64));
+        assert_eq!(IntegerType::narrowest(1 << 63), None);
+    }
+
+    #[test]
+    fn wraps_around_at_the_type() {
+        use IntegerType::*;
+        assert_eq!(Unsigned8.wrap(300), 44);
+        assert_eq!(Unsigned8.wrap(-1), 255);
+        assert_eq!(Signed8.wrap(200), -56);
+        assert_eq!(Signed8.wrap(-129), 127);
+        assert_eq!(Unsigned16.wrap(65536), 0);
+        assert_eq!(Signed32.wrap(1 << 31), i32::MIN as i64);
+        assert_eq!(Unsigned64.wrap(i64::MIN), i64::MIN);
+    }
+}
@@ -0,0 +1,1539 @@
+//! Server side of the protocol. Everything here runs with the `ServerKey` only: encrypted query
+//! literals are combined with the server's plaintext tables, which are trivially encrypted.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ops::Not;
+
+use tfhe::{
+    prelude::*, set_server_key, FheBool, FheInt16, FheInt32, FheInt64, FheInt8, FheUint16,
+    FheUint32, FheUint64, FheUint8, ServerKey,
+};
+
+use crate::query::{
+    Arithmetic, ArithmeticOp, ColumnRef, ComparisonOp, ConcatPart, EncryptedCell, EncryptedInteger,
+    EncryptedLiteral, EncryptedQuery, EncryptedResult, EncryptedRow, Operand, Predicate,
+    Projection, QueryError, TableSelector,
+};
+use crate::schema::IntegerType;
+use crate::tables::{Table, TableRow, Tables, ValueRef};
+
+/// This function will process an `EncryptedQuery` on set of data stored in `Tables`.
+/// The WHERE clause is evaluated homomorphically with the server key only: every row of the
+/// table gets an encrypted selection bit, and the server never learns which rows matched.
+/// Projected columns are picked with encrypted selectors, so it never learns which columns
+/// were asked for either.
+///
+/// When the table is encrypted, every table is scanned and the result is padded to the largest
+/// row count, column count and cell width, so the access pattern does not reveal the table.
+///
+/// The selected rows are then moved to the front of the result under encryption, so the server
+/// does not learn which rows matched from the result either.
+pub(crate) fn run_fhe_query(
+    sks: &ServerKey,
+    input: &EncryptedQuery,
+    data: &Tables,
+) -> Result<EncryptedResult, QueryError> {
+    set_server_key(sks.clone());
+
+    // Tables to scan, each with the encrypted flag telling whether it is the queried one. A table
+    // named in clear is the only one scanned and needs no flag.
+    let candidates: Vec<(&Table, Option<FheBool>)> = match &input.table {
+        TableSelector::Clear(name) => {
+            let table = data
+                .table(name)
+                .ok_or_else(|| QueryError::UnknownTable(name.clone()))?;
+            vec![(table, None)]
+        }
+        TableSelector::Encrypted(selector) => data
+            .iter()
+            .enumerate()
+            .map(|(position, table)| (table, Some(selector.eq(position as u8))))
+            .collect(),
+    };
+
+    // Every cell is padded to the widest value, so that a projected cell does not reveal which
+    // column or table it was taken from.
+    let row_count = candidates
+        .iter()
+        .map(|(table, _)| table.len())
+        .max()
+        .unwrap_or(0);
+    let width = candidates
+        .iter()
+        .map(|(table, _)| table.byte_width())
+        .max()
+        .unwrap_or(0);
+    let wildcard_width = candidates
+        .iter()
+        .map(|(table, _)| table.columns().len())
+        .max()
+        .unwrap_or(0);
+    let template = empty_row(&input.projection, wildcard_width, width)?;
+    let mut results = vec![template.clone(); row_count];
+
+    // The columns of a concatenation and the arithmetic results are gathered from every table
+    // first, and turned into cells once all the tables are scanned.
+    let mut gathered = vec![Gathered::new(&input.projection, width); row_count];
+
+    for (table, table_mask) in &candidates {
+        // The column masks only depend on the encrypted selectors, so they are computed once.
+        let masks: Vec<ColumnMasks> = input
+            .columns
+            .iter()
+            .map(|selector| ColumnMasks::new(selector, table.columns().len(), table_mask.as_ref()))
+            .collect();
+        let table_mask_byte = table_mask
+            .as_ref()
+            .map(|mask| FheUint8::cast_from(mask.clone()));
+
+        let rows = table
+            .rows()
+            .zip(results.iter_mut())
+            .zip(gathered.iter_mut());
+        for ((row, result), gathered) in rows {
+            let context = RowContext { row, masks: &masks };
+            let selected = match &input.selection {
+                Some(predicate) => evaluate_predicate(predicate, &context)?.holds,
+                None => FheBool::encrypt_trivial(true),
+            };
+            result.selected = match table_mask {
+                Some(mask) => &result.selected | &(selected & mask),
+                None => selected,
+            };
+
+            // The table data belongs to the server, so it is only trivially encrypted.
+            let values: Vec<Cow<[u8]>> = (0..table.columns().len())
+                .map(|column| row.bytes(column).unwrap_or_default())
+                .collect();
+
+            let mut cells = result.cells.iter_mut();
+            let mut strings = gathered.strings.iter_mut();
+            let mut integers = gathered.integers.iter_mut();
+            for item in &input.projection {
+                match item {
+                    Projection::Wildcard => {
+                        for position in 0..wildcard_width {
+                            if let (Some(cell), Some(value)) = (cells.next(), values.get(position))
+                            {
+                                add_masked(cell, table_mask_byte.as_ref(), value);
+                            }
+                        }
+                    }
+                    Projection::Column(slot) => {
+                        if let Some(cell) = cells.next() {
+                            add_column(cell, slot_masks(&masks, *slot)?, &values);
+                        }
+                    }
+                    Projection::Concat(parts) => {
+                        cells.next();
+                        // Only the columns have a gathered cell, the literals are added later.
+                        for part in parts {
+                            if let ConcatPart::Column(slot) = part {
+                                if let Some(cell) = strings.next() {
+                                    add_column(cell, slot_masks(&masks, *slot)?, &values);
+                                }
+                            }
+                        }
+                    }
+                    Projection::Arithmetic(arithmetic) => {
+                        cells.next();
+                        let (value, error) = evaluate_arithmetic(arithmetic, &context)?;
+                        if let Some((sum, sum_error)) = integers.next() {
+                            let value = value.encrypted(arithmetic.integer_type);
+                            // Only the queried table replaces the value.
+                            *sum = match table_mask {
+                                Some(mask) => TypedInteger::choose(mask, &value, sum)?,
+                                None => value,
+                            };
+                            let error = match (error, table_mask) {
+                                (Some(error), Some(mask)) => Some(error & mask),
+                                (error, _) => error,
+                            };
+                            *sum_error = any_error([sum_error.take(), error]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    for (result, gathered) in results.iter_mut().zip(gathered) {
+        let mut strings = gathered.strings.into_iter();
+        let mut integers = gathered.integers.into_iter();
+        let mut cell = 0;
+        for item in &input.projection {
+            match item {
+                Projection::Wildcard => cell += wildcard_width,
+                Projection::Column(_) => cell += 1,
+                Projection::Concat(parts) => {
+                    let parts: Vec<Vec<FheUint8>> = parts
+                        .iter()
+                        .map(|part| match part {
+                            ConcatPart::Column(_) => strings
+                                .next()
+                                .map(|column| column.bytes)
+                                .unwrap_or_default(),
+                            ConcatPart::Literal(bytes) => bytes.clone(),
+                        })
+                        .collect();
+                    result.cells[cell] = concatenate(&parts);
+                    cell += 1;
+                }
+                Projection::Arithmetic(arithmetic) => {
+                    if let Some((value, error)) = integers.next() {
+                        result.cells[cell] = integer_cell(value, error, arithmetic)?;
+                    }
+                    cell += 1;
+                }
+            }
+        }
+    }
+
+    if input.distinct {
+        deduplicate(&mut results);
+    }
+
+    // No more rows than scanned can be selected, and the client's value is not trusted to size
+    // the result.
+    let slot_count = input
+        .max_rows
+        .map_or(row_count, |max_rows| max_rows.min(row_count));
+    let (rows, count) = compact(&results, slot_count, &template);
+    Ok(EncryptedResult { rows, count })
+}
+
+// Values of a row gathered from every table, from which computed cells are built.
+#[derive(Clone)]
+struct Gathered {
+    // Columns of the concatenations, in projection order.
+    strings: Vec<EncryptedCell>,
+    // Results of the arithmetic expressions, in projection order, with their error flags.
+    integers: Vec<(TypedInteger, Option<FheBool>)>,
+}
+
+impl Gathered {
+    fn new(projection: &[Projection], width: usize) -> Gathered {
+        let mut gathered = Gathered {
+            strings: Vec::new(),
+            integers: Vec::new(),
+        };
+        for item in projection {
+            match item {
+                Projection::Concat(parts) => {
+                    for part in parts {
+                        if let ConcatPart::Column(_) = part {
+                            gathered.strings.push(trivial_cell(&[], width));
+                        }
+                    }
+                }
+                Projection::Arithmetic(arithmetic) => gathered
+                    .integers
+                    .push((TypedInteger::zero(arithmetic.integer_type), None)),
+                Projection::Wildcard | Projection::Column(_) => {}
+            }
+        }
+        gathered
+    }
+}
+
+// Encodes an arithmetic result like a projected integer, in little-endian order at the width of
+// its type. A NULL result is zeroed, so that NULLs are equal for SELECT DISTINCT.
+fn integer_cell(
+    mut value: TypedInteger,
+    error: Option<FheBool>,
+    arithmetic: &Arithmetic,
+) -> Result<EncryptedCell, QueryError> {
+    let error = arithmetic
+        .divides()
+        .then(|| error.unwrap_or_else(|| FheBool::encrypt_trivial(false)));
+    if let Some(error) = &error {
+        value = TypedInteger::choose(error, &TypedInteger::zero(arithmetic.integer_type), &value)?;
+    }
+    let bits = value.bits();
+    let width = (arithmetic.integer_type.bits() / 8) as usize;
+    let bytes = (0..width)
+        .map(|position| FheUint8::cast_from(&bits >> (8 * position) as u64))
+        .collect();
+    Ok(EncryptedCell {
+        bytes,
+        length: None,
+        error,
+    })
+}
+
+// Concatenates zero-padded strings under encryption. The length of a part is its number of
+// non-zero bytes, and each part starts at the sum of the lengths of the parts before it, an
+// encrypted offset. The part is moved there by a shift network: one stage per bit of the offset,
+// which shifts the bytes by that power of two when the bit is set. Padding bytes are zero, so
+// the bytes after the length stay zero and the shifted parts are merged with a bitwise or.
+fn concatenate(parts: &[Vec<FheUint8>]) -> EncryptedCell {
+    let capacity = parts.iter().map(Vec::len).sum();
+    let mut cell = trivial_cell(&[], capacity);
+    let mut length = FheUint16::encrypt_trivial(0u16);
+    let zero = FheUint8::encrypt_trivial(0u8);
+    // Bytes shifted in from before a part, or out of its end, are zero.
+    let byte = |bytes: &[FheUint8], index: Option<usize>| -> FheUint8 {
+        index
+            .and_then(|index| bytes.get(index))
+            .unwrap_or(&zero)
+            .clone()
+    };
+    let mut max_offset = 0;
+    for part in parts {
+        let mut shifted = part.clone();
+        let mut shift = 1;
+        while shift <= max_offset {
+            let bit = (&length & shift as u16).ne(0u16);
+            shifted = (0..shifted.len() + shift)
+                .map(|index| {
+                    bit.if_then_else(
+                        &byte(&shifted, index.checked_sub(shift)),
+                        &byte(&shifted, Some(index)),
+                    )
+                })
+                .collect();
+            shift *= 2;
+        }
+        for (byte, value) in cell.bytes.iter_mut().zip(&shifted) {
+            *byte |= value;
+        }
+        for byte in part {
+            length += FheUint16::cast_from(byte.ne(0u8));
+        }
+        max_offset += part.len();
+    }
+    cell.length = Some(length);
+    cell
+}
+
+// Oblivious compaction: moves the selected rows, in order, to the first of `slot_count` rows.
+// A selected row moves back by the number of unselected rows before it, through a shift
+// network with one stage per bit of that distance, lowest first. At each stage every row is
+// chosen under encryption between itself and the row that may move into its place: two selected
+// rows never meet, so O(n log n) choices are made whichever rows were selected. A row that moved
+// away leaves a stale copy behind, unselected, and unselected slots are emptied at the end.
+// Selected rows beyond the last slot are dropped, but still counted.
+fn compact(
+    rows: &[EncryptedRow],
+    slot_count: usize,
+    template: &EncryptedRow,
+) -> (Vec<EncryptedRow>, FheUint32) {
+    // The distance of a row is the number of unselected rows before it.
+    let mut unselected = FheUint32::encrypt_trivial(0u32);
+    let mut distances = Vec::with_capacity(rows.len());
+    for row in rows {
+        distances.push(unselected.clone());
+        unselected += FheUint32::cast_from(!&row.selected);
+    }
+    let count = FheUint32::encrypt_trivial(rows.len() as u32) - unselected;
+
+    let mut rows = rows.to_vec();
+    let mut shift = 1;
+    while shift < rows.len() {
+        let moves: Vec<FheBool> = rows
+            .iter()
+            .zip(&distances)
+            .map(|(row, distance)| &row.selected & (distance & shift as u32).ne(0u32))
+            .collect();
+        for index in 0..rows.len() {
+            let stays = &rows[index].selected & !&moves[index];
+            let (row, distance) = match rows.get(index + shift) {
+                Some(incoming) => {
+                    let moved = &moves[index + shift];
+                    let mut row = choose_row(moved, incoming, &rows[index]);
+                    row.selected = moved | &stays;
+                    (
+                        row,
+                        moved.if_then_else(&distances[index + shift], &distances[index]),
+                    )
+                }
+                None => {
+                    let mut row = rows[index].clone();
+                    row.selected = stays;
+                    (row, distances[index].clone())
+                }
+            };
+            rows[index] = row;
+            distances[index] = distance;
+        }
+        shift *= 2;
+    }
+
+    rows.truncate(slot_count);
+    rows.resize(slot_count, template.clone());
+    let slots = rows
+        .iter()
+        .map(|row| choose_row(&row.selected, row, template))
+        .collect();
+    (slots, count)
+}
+
+// Chooses between two rows of the same shape under encryption, cell by cell.
+fn choose_row(condition: &FheBool, then: &EncryptedRow, otherwise: &EncryptedRow) -> EncryptedRow {
+    let cells = then
+        .cells
+        .iter()
+        .zip(&otherwise.cells)
+        .map(|(then, otherwise)| EncryptedCell {
+            bytes: then
+                .bytes
+                .iter()
+                .zip(&otherwise.bytes)
+                .map(|(then, otherwise)| condition.if_then_else(then, otherwise))
+                .collect(),
+            length: match (&then.length, &otherwise.length) {
+                (Some(then), Some(otherwise)) => Some(condition.if_then_else(then, otherwise)),
+                _ => None,
+            },
+            error: match (&then.error, &otherwise.error) {
+                (Some(then), Some(otherwise)) => Some(choose_bool(condition, then, otherwise)),
+                _ => None,
+            },
+        })
+        .collect();
+    EncryptedRow {
+        selected: choose_bool(condition, &then.selected, &otherwise.selected),
+        cells,
+    }
+}
+
+fn choose_bool(condition: &FheBool, then: &FheBool, otherwise: &FheBool) -> FheBool {
+    (condition & then) | (!condition & otherwise)
+}
+
+// SELECT DISTINCT: clears the selection bit of every row whose projected cells are equal to the
+// cells of an earlier selected row. The comparison runs under encryption on every pair of rows,
+// whatever their selection bits, so nothing is learned about duplicates.
+fn deduplicate(rows: &mut [EncryptedRow]) {
+    for index in 1..rows.len() {
+        let (earlier, rest) = rows.split_at_mut(index);
+        let row = &mut rest[0];
+
+        let mut duplicate = FheBool::encrypt_trivial(false);
+        for other in earlier.iter() {
+            duplicate |= &other.selected & &same_cells(row, other);
+        }
+        row.selected &= !duplicate;
+    }
+}
+
+fn same_cells(left: &EncryptedRow, right: &EncryptedRow) -> FheBool {
+    let mut same = FheBool::encrypt_trivial(true);
+    for (left, right) in left.cells.iter().zip(&right.cells) {
+        for (left, right) in left.bytes.iter().zip(&right.bytes) {
+            same &= left.eq(right);
+        }
+        if let (Some(left), Some(right)) = (&left.error, &right.error) {
+            same &= !(left ^ right);
+        }
+    }
+    same
+}
+
+// Encrypted 0/1 masks telling which column of a table an encrypted column position designates.
+struct ColumnMasks {
+    // Masks for projected bytes, also zero when the table is not the queried one.
+    bytes: Vec<FheUint8>,
+    // Masks to rebuild the value of the column for comparisons. Values are rebuilt as `FheInt64`,
+    // the type SQLite stores integers in, which holds every signed and unsigned column type.
+    integers: Vec<FheInt64>,
+    // Masks to rebuild the value of a bool column, without the table mask.
+    bits: Vec<FheBool>,
+}
+
+impl ColumnMasks {
+    fn new(selector: &FheUint8, column_count: usize, table_mask: Option<&FheBool>) -> ColumnMasks {
+        let bits: Vec<FheBool> = (0..column_count)
+            .map(|position| selector.eq(position as u8))
+            .collect();
+        let bytes = bits
+            .iter()
+            .map(|bit| match table_mask {
+                Some(mask) => FheUint8::cast_from(bit & mask),
+                None => FheUint8::cast_from(bit.clone()),
+            })
+            .collect();
+        let integers = bits.iter().cloned().map(FheInt64::cast_from).collect();
+        ColumnMasks {
+            bytes,
+            integers,
+            bits,
+        }
+    }
+}
+
+// Adds the value of the column designated by `masks` to the cell.
+fn add_column(cell: &mut EncryptedCell, masks: &ColumnMasks, values: &[Cow<[u8]>]) {
+    for (mask, value) in masks.bytes.iter().zip(values) {
+        add_masked(cell, Some(mask), value);
+    }
+}
+
+fn slot_masks(masks: &[ColumnMasks], slot: usize) -> Result<&ColumnMasks, QueryError> {
+    masks
+        .get(slot)
+        .ok_or_else(|| QueryError::Malformed(format!("unknown column slot {}", slot)))
+}
+
+// Adds `mask * value` to the cell, or the value itself when there is no mask.
+fn add_masked(cell: &mut EncryptedCell, mask: Option<&FheUint8>, value: &[u8]) {
+    for (byte, &clear) in cell.bytes.iter_mut().zip(value) {
+        if clear == 0 {
+            continue;
+        }
+        match mask {
+            Some(mask) => *byte += mask * clear,
+            None => *byte += clear,
+        }
+    }
+}
+
+// A result row with no value: every cell is `width` bytes of zeros, except concatenations, which
+// have room for all their parts.
+fn empty_row(
+    projection: &[Projection],
+    wildcard_width: usize,
+    width: usize,
+) -> Result<EncryptedRow, QueryError> {
+    let mut cells = Vec::new();
+    for item in projection {
+        match item {
+            Projection::Wildcard => {
+                cells.extend((0..wildcard_width).map(|_| trivial_cell(&[], width)));
+            }
+            Projection::Column(_) => cells.push(trivial_cell(&[], width)),
+            Projection::Concat(parts) => {
+                let capacity: usize = parts
+                    .iter()
+                    .map(|part| match part {
+                        ConcatPart::Column(_) => width,
+                        ConcatPart::Literal(bytes) => bytes.len(),
+                    })
+                    .sum();
+                // The length is encrypted as an `FheUint16`.
+                if capacity > u16::MAX as usize {
+                    return Err(QueryError::Unsupported(format!(
+                        "concatenation of more than {} bytes",
+                        u16::MAX
+                    )));
+                }
+                let mut cell = trivial_cell(&[], capacity);
+                cell.length = Some(FheUint16::encrypt_trivial(0u16));
+                cells.push(cell);
+            }
+            Projection::Arithmetic(arithmetic) => {
+                let width = (arithmetic.integer_type.bits() / 8) as usize;
+                let mut cell = trivial_cell(&[], width);
+                cell.error = arithmetic
+                    .divides()
+                    .then(|| FheBool::encrypt_trivial(false));
+                cells.push(cell);
+            }
+        }
+    }
+    Ok(EncryptedRow {
+        selected: FheBool::encrypt_trivial(false),
+        cells,
+    })
+}
+
+fn trivial_cell(value: &[u8], width: usize) -> EncryptedCell {
+    let bytes = (0..width)
+        .map(|position| FheUint8::encrypt_trivial(value.get(position).copied().unwrap_or(0)))
+        .collect();
+    EncryptedCell {
+        bytes,
+        length: None,
+        error: None,
+    }
+}
+
+// The row being evaluated, with the column masks of its table.
+struct RowContext<'a> {
+    row: TableRow<'a>,
+    masks: &'a [ColumnMasks],
+}
+
+// Value of a column for the current row.
+enum RowValue<'a> {
+    Clear(ValueRef<'a>),
+    // Integer rebuilt under encryption from an encrypted column position.
+    Integer(FheInt64),
+    // String bytes rebuilt under encryption from an encrypted column position.
+    Bytes(Vec<FheUint8>),
+    // Bool rebuilt under encryption from an encrypted column position.
+    Boolean(FheBool),
+}
+
+impl<'a> RowContext<'a> {
+    fn cell(&self, name: &str) -> Result<ValueRef<'a>, QueryError> {
+        self.row
+            .get(name)
+            .ok_or_else(|| QueryError::UnknownColumn(name.to_string()))
+    }
+
+    // Value of the column in the representation needed to compare it with `literal`.
+    fn value_for(
+        &self,
+        column: &ColumnRef,
+        literal: &EncryptedLiteral,
+    ) -> Result<RowValue<'a>, QueryError> {
+        match literal {
+            // One more byte than the literal, so that longer values never compare equal.
+            EncryptedLiteral::String(bytes) => self.string_value(column, bytes.len() + 1),
+            EncryptedLiteral::Integer(_) => self.integer_value(column),
+            EncryptedLiteral::Boolean(_) => self.boolean_value(column),
+        }
+    }
+
+    fn integer_value(&self, column: &ColumnRef) -> Result<RowValue<'a>, QueryError> {
+        match column {
+            ColumnRef::Name(name) => Ok(RowValue::Clear(self.cell(name)?)),
+            ColumnRef::Slot(slot) => {
+                let masks = slot_masks(self.masks, *slot)?;
+                let mut value = FheInt64::encrypt_trivial(0i64);
+                for (position, mask) in masks.integers.iter().enumerate() {
+                    // Bools count as 0 or 1, as SQLite stores them. Strings cannot be compared to
+                    // an integer and add nothing.
+                    match self.row.value(position) {
+                        Some(ValueRef::Integer(clear)) if clear != 0 => value += mask * clear,
+                        Some(ValueRef::Boolean(true)) => value += mask,
+                        _ => {}
+                    }
+                }
+                Ok(RowValue::Integer(value))
+            }
+        }
+    }
+
+    fn boolean_value(&self, column: &ColumnRef) -> Result<RowValue<'a>, QueryError> {
+        match column {
+            ColumnRef::Name(name) => Ok(RowValue::Clear(self.cell(name)?)),
+            ColumnRef::Slot(slot) => {
+                let masks = slot_masks(self.masks, *slot)?;
+                let mut value = FheBool::encrypt_trivial(false);
+                for (position, mask) in masks.bits.iter().enumerate() {
+                    if let Some(ValueRef::Boolean(true)) = self.row.value(position) {
+                        value |= mask;
+                    }
+                }
+                Ok(RowValue::Boolean(value))
+            }
+        }
+    }
+
+    // Value of an operand of an arithmetic expression, or of a comparison between expressions,
+    // with the flag of a division by zero computing it.
+    fn integer_operand(
+        &self,
+        operand: &Operand,
+    ) -> Result<(RowValue<'a>, Option<FheBool>), QueryError> {
+        match operand {
+            Operand::Column(column) => Ok((self.integer_value(column)?, None)),
+            Operand::Literal(EncryptedLiteral::Integer(literal)) => {
+                Ok((RowValue::Integer(widen(literal)), None))
+            }
+            Operand::Literal(_) => Err(QueryError::Unsupported(
+                "non-integer literal in an integer expression".to_string(),
+            )),
+            Operand::Arithmetic(arithmetic) => {
+                let (value, error) = evaluate_arithmetic(arithmetic, self)?;
+                Ok((value.widened(), error))
+            }
+        }
+    }
+
+    // Value of an operand of an arithmetic expression computed at `integer_type`, with the flag
+    // of a division by zero computing it.
+    fn arithmetic_operand(
+        &self,
+        operand: &Operand,
+        integer_type: IntegerType,
+    ) -> Result<(ArithmeticValue, Option<FheBool>), QueryError> {
+        match operand {
+            Operand::Column(ColumnRef::Name(name)) => Ok((
+                ArithmeticValue::Clear(integer(self.cell(name)?)? as i64),
+                None,
+            )),
+            Operand::Column(ColumnRef::Slot(slot)) => {
+                let masks = slot_masks(self.masks, *slot)?;
+                let value = TypedInteger::column(masks, &self.row, integer_type);
+                Ok((ArithmeticValue::Encrypted(value), None))
+            }
+            Operand::Literal(EncryptedLiteral::Integer(literal)) => Ok((
+                ArithmeticValue::Encrypted(typed(literal).cast(integer_type)),
+                None,
+            )),
+            Operand::Literal(_) => Err(QueryError::Unsupported(
+                "non-integer literal in an integer expression".to_string(),
+            )),
+            Operand::Arithmetic(arithmetic) => evaluate_arithmetic(arithmetic, self),
+        }
+    }
+
+    fn string_value(&self, column: &ColumnRef, width: usize) -> Result<RowValue<'a>, QueryError> {
+        match column {
+            ColumnRef::Name(name) => Ok(RowValue::Clear(self.cell(name)?)),
+            ColumnRef::Slot(slot) => {
+                let masks = slot_masks(self.masks, *slot)?;
+                let mut value = trivial_cell(&[], width);
+                for (position, mask) in masks.bytes.iter().enumerate() {
+                    if let Some(cell) = self.row.bytes(position) {
+                        add_masked(&mut value, Some(mask), &cell);
+                    }
+                }
+                Ok(RowValue::Bytes(value.bytes))
+            }
+        }
+    }
+}
+
+// Encrypted truth value of a predicate under the three-valued logic of SQL: a comparison with a
+// division by zero is unknown, like a comparison with NULL, and so is its negation.
+struct Truth {
+    // The predicate is known to hold. Only such rows are selected.
+    holds: FheBool,
+    // The predicate is known not to hold. `None` when it cannot be unknown, so that it fails
+    // exactly when it does not hold.
+    fails: Option<FheBool>,
+}
+
+impl Truth {
+    fn known(holds: FheBool) -> Truth {
+        Truth { holds, fails: None }
+    }
+
+    // A comparison that is unknown when `error` is set.
+    fn unless_error(holds: FheBool, error: Option<FheBool>) -> Truth {
+        match error {
+            None => Truth::known(holds),
+            Some(error) => {
+                let valid = !error;
+                Truth {
+                    holds: &holds & &valid,
+                    fails: Some(!holds & valid),
+                }
+            }
+        }
+    }
+
+    fn fails(&self) -> FheBool {
+        match &self.fails {
+            Some(fails) => fails.clone(),
+            None => !self.holds.clone(),
+        }
+    }
+
+    fn and(self, other: Truth) -> Truth {
+        if self.fails.is_none() && other.fails.is_none() {
+            return Truth::known(self.holds & other.holds);
+        }
+        let fails = self.fails() | other.fails();
+        Truth {
+            holds: self.holds & other.holds,
+            fails: Some(fails),
+        }
+    }
+
+    fn or(self, other: Truth) -> Truth {
+        if self.fails.is_none() && other.fails.is_none() {
+            return Truth::known(self.holds | other.holds);
+        }
+        let fails = self.fails() & other.fails();
+        Truth {
+            holds: self.holds | other.holds,
+            fails: Some(fails),
+        }
+    }
+}
+
+impl Not for Truth {
+    type Output = Truth;
+
+    fn not(self) -> Truth {
+        match self.fails {
+            None => Truth::known(!self.holds),
+            Some(fails) => Truth {
+                holds: fails,
+                fails: Some(self.holds),
+            },
+        }
+    }
+}
+
+// Evaluates a predicate tree against one row, producing an encrypted selection bit.
+fn evaluate_predicate(predicate: &Predicate, row: &RowContext) -> Result<Truth, QueryError> {
+    match predicate {
+        Predicate::And(left, right) => {
+            Ok(evaluate_predicate(left, row)?.and(evaluate_predicate(right, row)?))
+        }
+        Predicate::Or(left, right) => {
+            Ok(evaluate_predicate(left, row)?.or(evaluate_predicate(right, row)?))
+        }
+        Predicate::Not(inner) => Ok(!evaluate_predicate(inner, row)?),
+        Predicate::Compare { left, op, right } => evaluate_comparison(left, *op, right, row),
+        Predicate::InList {
+            expr,
+            list,
+            negated,
+        } => evaluate_in_list(expr, list, *negated, row),
+        Predicate::Between {
+            expr,
+            low,
+            high,
+            negated,
+        } => Ok(Truth::known(evaluate_between(
+            expr, low, high, *negated, row,
+        )?)),
+        Predicate::Column(column) => match row.boolean_value(column)? {
+            RowValue::Clear(ValueRef::Boolean(value)) => {
+                Ok(Truth::known(FheBool::encrypt_trivial(value)))
+            }
+            RowValue::Boolean(value) => Ok(Truth::known(value)),
+            _ => Err(QueryError::Malformed(
+                "non-bool column used as a predicate".to_string(),
+            )),
+        },
+    }
+}
+
+// Evaluates a comparison node between encrypted literals and the row's values.
+fn evaluate_comparison(
+    left: &Operand,
+    op: ComparisonOp,
+    right: &Operand,
+    row: &RowContext,
+) -> Result<Truth, QueryError> {
+    match (left, right) {
+        (Operand::Literal(literal), Operand::Column(column)) => Ok(Truth::known(compare_literal(
+            literal,
+            op,
+            &row.value_for(column, literal)?,
+        )?)),
+        // `column op literal` is evaluated as `literal flipped(op) column`.
+        (Operand::Column(column), Operand::Literal(literal)) => Ok(Truth::known(compare_literal(
+            literal,
+            op.flip(),
+            &row.value_for(column, literal)?,
+        )?)),
+        (Operand::Literal(_), Operand::Literal(_)) => Err(QueryError::Unsupported(
+            "comparison between two literals".to_string(),
+        )),
+        // Columns and arithmetic results are compared as integers.
+        (left, right) => {
+            let (left, left_error) = row.integer_operand(left)?;
+            let (right, right_error) = row.integer_operand(right)?;
+            let holds = compare_values(left, op, right)?;
+            Ok(Truth::unless_error(
+                holds,
+                any_error([left_error, right_error]),
+            ))
+        }
+    }
+}
+
+// Evaluates an arithmetic expression for the row. The result is computed at the width of the
+// type of the expression, and wraps around at it. The flag is set when a division or a modulo by
+// zero occurred: the result is then NULL, and its value meaningless.
+fn evaluate_arithmetic(
+    arithmetic: &Arithmetic,
+    row: &RowContext,
+) -> Result<(ArithmeticValue, Option<FheBool>), QueryError> {
+    // A literal that does not fit in the type is encrypted with a wider one, and the expression
+    // is then computed at that width and wrapped around, so that dividing by it is still exact.
+    let integer_type = arithmetic.integer_type;
+    let computed_type = [&arithmetic.left, &arithmetic.right].into_iter().fold(
+        integer_type,
+        |computed_type, operand| match operand {
+            Operand::Literal(EncryptedLiteral::Integer(literal)) => {
+                computed_type.promote(literal_type(literal))
+            }
+            _ => computed_type,
+        },
+    );
+    let (left, left_error) = row.arithmetic_operand(&arithmetic.left, computed_type)?;
+    let (right, right_error) = row.arithmetic_operand(&arithmetic.right, computed_type)?;
+    let (value, error) = match (left, right) {
+        // Both values belong to the server, so the result is computed in clear.
+        (ArithmeticValue::Clear(left), ArithmeticValue::Clear(right)) => {
+            let (value, error) = match arithmetic.op {
+                ArithmeticOp::Add => (left.wrapping_add(right), false),
+                ArithmeticOp::Subtract => (left.wrapping_sub(right), false),
+                ArithmeticOp::Multiply => (left.wrapping_mul(right), false),
+                ArithmeticOp::Divide | ArithmeticOp::Modulo if right == 0 => (0, true),
+                ArithmeticOp::Divide => (left.wrapping_div(right), false),
+                ArithmeticOp::Modulo => (left.wrapping_rem(right), false),
+            };
+            (
+                ArithmeticValue::Clear(integer_type.wrap(value)),
+                error.then(|| FheBool::encrypt_trivial(true)),
+            )
+        }
+        (left, right) => {
+            let left = left.encrypted(computed_type);
+            let right = right.encrypted(computed_type);
+            let (value, error) = left.compute(arithmetic.op, &right)?;
+            (ArithmeticValue::Encrypted(value.cast(integer_type)), error)
+        }
+    };
+    Ok((value, any_error([left_error, right_error, error])))
+}
+
+fn literal_type(literal: &EncryptedInteger) -> IntegerType {
+    match literal {
+        EncryptedInteger::U8(_) => IntegerType::Unsigned8,
+        EncryptedInteger::U16(_) => IntegerType::Unsigned16,
+        EncryptedInteger::U32(_) => IntegerType::Unsigned32,
+        EncryptedInteger::U64(_) => IntegerType::Unsigned64,
+        EncryptedInteger::I8(_) => IntegerType::Signed8,
+        EncryptedInteger::I16(_) => IntegerType::Signed16,
+        EncryptedInteger::I32(_) => IntegerType::Signed32,
+        EncryptedInteger::I64(_) => IntegerType::Signed64,
+    }
+}
+
+// Value of an arithmetic expression or operand: in clear while it only depends on the server's
+// values, wrapped around at the type of the expression.
+enum ArithmeticValue {
+    Clear(i64),
+    Encrypted(TypedInteger),
+}
+
+impl ArithmeticValue {
+    fn encrypted(self, integer_type: IntegerType) -> TypedInteger {
+        match self {
+            ArithmeticValue::Clear(value) => TypedInteger::trivial(value, integer_type),
+            ArithmeticValue::Encrypted(value) => value.cast(integer_type),
+        }
+    }
+
+    // The value as a row value, widened to be compared.
+    fn widened<'a>(self) -> RowValue<'a> {
+        match self {
+            ArithmeticValue::Clear(value) => RowValue::Clear(ValueRef::Integer(value)),
+            ArithmeticValue::Encrypted(value) => RowValue::Integer(value.widened()),
+        }
+    }
+}
+
+// An encrypted integer at the width of its type. `uint64` values are kept in the int64 range,
+// see `IntegerType::wrap`, so they are `FheInt64` as well.
+#[derive(Clone)]
+enum TypedInteger {
+    I8(FheInt8),
+    U8(FheUint8),
+    I16(FheInt16),
+    U16(FheUint16),
+    I32(FheInt32),
+    U32(FheUint32),
+    I64(FheInt64),
+}
+
+// Evaluates `$body` with `$fhe` and `$clear` the encrypted and clear types of `$integer_type`,
+// and wraps the `$fhe` value it evaluates to.
+macro_rules! at_width {
+    ($integer_type:expr, $fhe:ident, $clear:ident => $body:expr) => {
+        match $integer_type {
+            IntegerType::Signed8 => {
+                type $fhe = FheInt8;
+                type $clear = i8;
+                TypedInteger::I8($body)
+            }
+            IntegerType::Unsigned8 => {
+                type $fhe = FheUint8;
+                type $clear = u8;
+                TypedInteger::U8($body)
+            }
+            IntegerType::Signed16 => {
+                type $fhe = FheInt16;
+                type $clear = i16;
+                TypedInteger::I16($body)
+            }
+            IntegerType::Unsigned16 => {
+                type $fhe = FheUint16;
+                type $clear = u16;
+                TypedInteger::U16($body)
+            }
+            IntegerType::Signed32 => {
+                type $fhe = FheInt32;
+                type $clear = i32;
+                TypedInteger::I32($body)
+            }
+            IntegerType::Unsigned32 => {
+                type $fhe = FheUint32;
+                type $clear = u32;
+                TypedInteger::U32($body)
+            }
+            IntegerType::Signed64 | IntegerType::Unsigned64 => {
+                type $fhe = FheInt64;
+                type $clear = i64;
+                TypedInteger::I64($body)
+            }
+        }
+    };
+}
+
+// Evaluates `$body` with `$value` bound to the ciphertext of `$typed`, whatever its width.
+macro_rules! any_width {
+    ($typed:expr, $value:ident => $body:expr) => {
+        match $typed {
+            TypedInteger::I8($value) => $body,
+            TypedInteger::U8($value) => $body,
+            TypedInteger::I16($value) => $body,
+            TypedInteger::U16($value) => $body,
+            TypedInteger::I32($value) => $body,
+            TypedInteger::U32($value) => $body,
+            TypedInteger::I64($value) => $body,
+        }
+    };
+}
+
+// Evaluates `$body`, of the type of its operands, with `$left` and `$right` bound to the
+// ciphertexts of two values of the same width.
+macro_rules! same_width {
+    ($left:expr, $right:expr, $l:ident, $r:ident => $body:expr) => {
+        match ($left, $right) {
+            (TypedInteger::I8($l), TypedInteger::I8($r)) => Ok(TypedInteger::I8($body)),
+            (TypedInteger::U8($l), TypedInteger::U8($r)) => Ok(TypedInteger::U8($body)),
+            (TypedInteger::I16($l), TypedInteger::I16($r)) => Ok(TypedInteger::I16($body)),
+            (TypedInteger::U16($l), TypedInteger::U16($r)) => Ok(TypedInteger::U16($body)),
+            (TypedInteger::I32($l), TypedInteger::I32($r)) => Ok(TypedInteger::I32($body)),
+            (TypedInteger::U32($l), TypedInteger::U32($r)) => Ok(TypedInteger::U32($body)),
+            (TypedInteger::I64($l), TypedInteger::I64($r)) => Ok(TypedInteger::I64($body)),
+            _ => Err(QueryError::Malformed(
+                "integers of different widths combined".to_string(),
+            )),
+        }
+    };
+}
+
+impl TypedInteger {
+    fn zero(integer_type: IntegerType) -> TypedInteger {
+        TypedInteger::trivial(0, integer_type)
+    }
+
+    // Value of the column of a row picked by the masks, rebuilt at the type.
+    fn column(masks: &ColumnMasks, row: &TableRow, integer_type: IntegerType) -> TypedInteger {
+        at_width!(integer_type, Fhe, Clear => {
+            let mut value = Fhe::encrypt_trivial(0 as Clear);
+            for (position, mask) in masks.bits.iter().enumerate() {
+                // Bools count as 0 or 1, as SQLite stores them. Strings add nothing.
+                match row.value(position) {
+                    Some(ValueRef::Integer(clear)) if clear != 0 => {
+                        value += Fhe::cast_from(mask.clone()) * clear as Clear
+                    }
+                    Some(ValueRef::Boolean(true)) => value += Fhe::cast_from(mask.clone()),
+                    _ => {}
+                }
+            }
+            value
+        })
+    }
+
+    // A value of the server, wrapped around to the type.
+    fn trivial(value: i64, integer_type: IntegerType) -> TypedInteger {
+        at_width!(integer_type, Fhe, Clear => Fhe::encrypt_trivial(value as Clear))
+    }
+
+    // Casts to another type, wrapping around when it is narrower.
+    fn cast(self, integer_type: IntegerType) -> TypedInteger {
+        if self.has_type(integer_type) {
+            return self;
+        }
+        any_width!(self, value => at_width!(integer_type, Fhe, _Clear => Fhe::cast_from(value)))
+    }
+
+    fn has_type(&self, integer_type: IntegerType) -> bool {
+        matches!(
+            (self, integer_type),
+            (TypedInteger::I8(_), IntegerType::Signed8)
+                | (TypedInteger::U8(_), IntegerType::Unsigned8)
+                | (TypedInteger::I16(_), IntegerType::Signed16)
+                | (TypedInteger::U16(_), IntegerType::Unsigned16)
+                | (TypedInteger::I32(_), IntegerType::Signed32)
+                | (TypedInteger::U32(_), IntegerType::Unsigned32)
+                | (
+                    TypedInteger::I64(_),
+                    IntegerType::Signed64 | IntegerType::Unsigned64
+                )
+        )
+    }
+
+    fn widened(self) -> FheInt64 {
+        any_width!(self, value => FheInt64::cast_from(value))
+    }
+
+    // The two's complement bits of the value, sign-extended to 64 bits.
+    fn bits(self) -> FheUint64 {
+        any_width!(self, value => FheUint64::cast_from(value))
+    }
+
+    fn choose(
+        condition: &FheBool,
+        then: &TypedInteger,
+        otherwise: &TypedInteger,
+    ) -> Result<TypedInteger, QueryError> {
+        same_width!(then, otherwise, then, otherwise => condition.if_then_else(then, otherwise))
+    }
+
+    // Computes `self op right`, with the flag of a division by zero.
+    fn compute(
+        &self,
+        op: ArithmeticOp,
+        right: &TypedInteger,
+    ) -> Result<(TypedInteger, Option<FheBool>), QueryError> {
+        let value = same_width!(self, right, left, right => match op {
+            ArithmeticOp::Add => left + right,
+            ArithmeticOp::Subtract => left - right,
+            ArithmeticOp::Multiply => left * right,
+            ArithmeticOp::Divide => left / right,
+            ArithmeticOp::Modulo => left % right,
+        })?;
+        let error = match op {
+            ArithmeticOp::Divide | ArithmeticOp::Modulo => {
+                Some(any_width!(right, right => right.eq(0)))
+            }
+            _ => None,
+        };
+        Ok((value, error))
+    }
+}
+
+// Whether any of the error flags is set, or `None` when none can be.
+fn any_error<const N: usize>(errors: [Option<FheBool>; N]) -> Option<FheBool> {
+    errors
+        .into_iter()
+        .flatten()
+        .reduce(|any, error| any | error)
+}
+
+// `expr [NOT] IN (...)` is the OR of the encrypted equalities between the row value and each
+// literal of the list. It is unknown when `expr` divides by zero.
+fn evaluate_in_list(
+    expr: &Operand,
+    list: &[EncryptedLiteral],
+    negated: bool,
+    row: &RowContext,
+) -> Result<Truth, QueryError> {
+    let mut found = FheBool::encrypt_trivial(false);
+    let mut error = None;
+    if let Some(first) = list.first() {
+        // The literals all have the type of `expr`, so the row value is only rebuilt once.
+        let value = match expr {
+            Operand::Column(column) => row.value_for(column, first)?,
+            Operand::Arithmetic(_) => {
+                let (value, arithmetic_error) = row.integer_operand(expr)?;
+                error = arithmetic_error;
+                value
+            }
+            Operand::Literal(_) => {
+                return Err(QueryError::Unsupported(
+                    "IN applied to a literal".to_string(),
+                ))
+            }
+        };
+        for literal in list {
+            found |= compare_literal(literal, ComparisonOp::Eq, &value)?;
+        }
+    }
+    Ok(Truth::unless_error(
+        if negated { !found } else { found },
+        error,
+    ))
+}
+
+// `column [NOT] BETWEEN low AND high` is `column >= low & column <= high`, evaluated as
+// `low <= column & high >= column` against the encrypted bounds.
+fn evaluate_between(
+    expr: &Operand,
+    low: &Operand,
+    high: &Operand,
+    negated: bool,
+    row: &RowContext,
+) -> Result<FheBool, QueryError> {
+    let (column, low, high) = match (expr, low, high) {
+        (Operand::Column(column), Operand::Literal(low), Operand::Literal(high)) => {
+            (column, low, high)
+        }
+        _ => {
+            return Err(QueryError::Unsupported(
+                "BETWEEN bounds must be literals compared with a column".to_string(),
+            ))
+        }
+    };
+
+    // Both bounds have the column's type, so the row value is only rebuilt once.
+    let value = row.value_for(column, low)?;
+    let inside = compare_literal(low, ComparisonOp::LtEq, &value)?
+        & compare_literal(high, ComparisonOp::GtEq, &value)?;
+    Ok(if negated { !inside } else { inside })
+}
+
+fn integer(value: ValueRef) -> Result<i128, QueryError> {
+    match value {
+        ValueRef::Integer(value) => Ok(value as i128),
+        ValueRef::Boolean(value) => Ok(value as i128),
+        other => Err(QueryError::Unsupported(format!(
+            "non-integer value `{:?}`",
+            other
+        ))),
+    }
+}
+
+// Computes `literal op value` for an encrypted literal and a row value.
+fn compare_literal(
+    literal: &EncryptedLiteral,
+    op: ComparisonOp,
+    value: &RowValue,
+) -> Result<FheBool, QueryError> {
+    let literal = match literal {
+        EncryptedLiteral::Integer(literal) => literal,
+        EncryptedLiteral::Boolean(literal) => return compare_booleans(literal, op, value),
+        // Strings only compare for equality.
+        EncryptedLiteral::String(bytes) => {
+            return match op {
+                ComparisonOp::Eq => string_equals(bytes, value),
+                ComparisonOp::NotEq => Ok(!string_equals(bytes, value)?),
+                _ => Err(QueryError::Unsupported(
+                    "strings only compare with = and <>".to_string(),
+                )),
+            }
+        }
+    };
+    match value {
+        RowValue::Clear(value) => {
+            let value = integer(*value)?;
+            Ok(match literal {
+                EncryptedInteger::U8(ct) => compare(ct, op, fit::<u8>(value)),
+                EncryptedInteger::U16(ct) => compare(ct, op, fit::<u16>(value)),
+                EncryptedInteger::U32(ct) => compare(ct, op, fit::<u32>(value)),
+                EncryptedInteger::U64(ct) => compare(ct, op, fit::<u64>(value)),
+                EncryptedInteger::I8(ct) => compare(ct, op, fit::<i8>(value)),
+                EncryptedInteger::I16(ct) => compare(ct, op, fit::<i16>(value)),
+                EncryptedInteger::I32(ct) => compare(ct, op, fit::<i32>(value)),
+                EncryptedInteger::I64(ct) => compare(ct, op, fit::<i64>(value)),
+            })
+        }
+        RowValue::Integer(value) => Ok(compare(&widen(literal), op, Ok(value))),
+        RowValue::Bytes(_) => Err(QueryError::Malformed(
+            "integer literal compared with a string".to_string(),
+        )),
+        RowValue::Boolean(_) => Err(QueryError::Malformed(
+            "integer literal compared with a bool".to_string(),
+        )),
+    }
+}
+
+// Computes `literal op value` for a bool literal, with `false < true` as in SQLite.
+fn compare_booleans(
+    literal: &FheBool,
+    op: ComparisonOp,
+    value: &RowValue,
+) -> Result<FheBool, QueryError> {
+    let value = match value {
+        RowValue::Clear(ValueRef::Boolean(value)) => FheBool::encrypt_trivial(*value),
+        RowValue::Boolean(value) => value.clone(),
+        _ => {
+            return Err(QueryError::Malformed(
+                "bool literal compared with a non-bool column".to_string(),
+            ))
+        }
+    };
+    Ok(match op {
+        ComparisonOp::Eq => !(literal ^ &value),
+        ComparisonOp::NotEq => literal ^ &value,
+        ComparisonOp::Lt => !literal & &value,
+        ComparisonOp::LtEq => !literal | &value,
+        ComparisonOp::Gt => literal & !value,
+        ComparisonOp::GtEq => literal | !value,
+    })
+}
+
+// Encrypted equality between a zero-padded string literal and a row value.
+fn string_equals(literal: &[FheUint8], value: &RowValue) -> Result<FheBool, QueryError> {
+    let mut equal = FheBool::encrypt_trivial(true);
+    match value {
+        RowValue::Clear(value) => {
+            let value = match value {
+                ValueRef::String(bytes) => *bytes,
+                _ => {
+                    return Err(QueryError::Unsupported(
+                        "string literal compared with a non-string column".to_string(),
+                    ))
+                }
+            };
+            // A value longer than the padded literal cannot be equal to it.
+            if value.len() > literal.len() {
+                return Ok(FheBool::encrypt_trivial(false));
+            }
+            for (position, byte) in literal.iter().enumerate() {
+                equal &= byte.eq(value.get(position).copied().unwrap_or(0));
+            }
+        }
+        RowValue::Bytes(value) => {
+            // The value carries one byte more than the literal, which has to be padding.
+            for (position, byte) in value.iter().enumerate() {
+                match literal.get(position) {
+                    Some(literal) => equal &= literal.eq(byte),
+                    None => equal &= byte.eq(0u8),
+                }
+            }
+        }
+        RowValue::Integer(_) | RowValue::Boolean(_) => {
+            return Err(QueryError::Malformed(
+                "string literal compared with a non-string column".to_string(),
+            ))
+        }
+    }
+    Ok(equal)
+}
+
+// Computes `left op right` for two row values.
+fn compare_values(
+    left: RowValue,
+    op: ComparisonOp,
+    right: RowValue,
+) -> Result<FheBool, QueryError> {
+    Ok(match (left, right) {
+        (RowValue::Clear(left), RowValue::Clear(right)) => {
+            let ordering = integer(left)?.cmp(&integer(right)?);
+            FheBool::encrypt_trivial(op.holds(ordering))
+        }
+        (RowValue::Integer(left), RowValue::Clear(right)) => {
+            compare(&left, op, fit::<i64>(integer(right)?))
+        }
+        (RowValue::Clear(left), RowValue::Integer(right)) => {
+            compare(&right, op.flip(), fit::<i64>(integer(left)?))
+        }
+        (RowValue::Integer(left), RowValue::Integer(right)) => compare(&left, op, Ok(&right)),
+        (RowValue::Bytes(_), _) | (_, RowValue::Bytes(_)) => {
+            return Err(QueryError::Unsupported(
+                "comparison between two string columns".to_string(),
+            ))
+        }
+        // Columns are rebuilt as integers for these comparisons, bools included.
+        (RowValue::Boolean(_), _) | (_, RowValue::Boolean(_)) => {
+            return Err(QueryError::Malformed(
+                "bool compared as an integer".to_string(),
+            ))
+        }
+    })
+}
+
+// Casts an integer literal to the type of the values rebuilt from encrypted column positions.
+// Unsigned literals are zero-extended and signed ones sign-extended, so the order is preserved.
+// A literal as a typed integer. `uint64` literals are kept in the int64 range.
+fn typed(literal: &EncryptedInteger) -> TypedInteger {
+    match literal {
+        EncryptedInteger::U8(ct) => TypedInteger::U8(ct.clone()),
+        EncryptedInteger::U16(ct) => TypedInteger::U16(ct.clone()),
+        EncryptedInteger::U32(ct) => TypedInteger::U32(ct.clone()),
+        EncryptedInteger::U64(ct) => TypedInteger::I64(FheInt64::cast_from(ct.clone())),
+        EncryptedInteger::I8(ct) => TypedInteger::I8(ct.clone()),
+        EncryptedInteger::I16(ct) => TypedInteger::I16(ct.clone()),
+        EncryptedInteger::I32(ct) => TypedInteger::I32(ct.clone()),
+        EncryptedInteger::I64(ct) => TypedInteger::I64(ct.clone()),
+    }
+}
+
+fn widen(literal: &EncryptedInteger) -> FheInt64 {
+    match literal {
+        EncryptedInteger::U8(ct) => FheInt64::cast_from(ct.clone()),
+        EncryptedInteger::U16(ct) => FheInt64::cast_from(ct.clone()),
+        EncryptedInteger::U32(ct) => FheInt64::cast_from(ct.clone()),
+        EncryptedInteger::U64(ct) => FheInt64::cast_from(ct.clone()),
+        EncryptedInteger::I8(ct) => FheInt64::cast_from(ct.clone()),
+        EncryptedInteger::I16(ct) => FheInt64::cast_from(ct.clone()),
+        EncryptedInteger::I32(ct) => FheInt64::cast_from(ct.clone()),
+        EncryptedInteger::I64(ct) => ct.clone(),
+    }
+}
+
+// Converts a row value to the clear type of a literal. A value out of that type's range is
+// either above or below every literal, and the error tells how the literal compares to it.
+fn fit<C: TryFrom<i128>>(value: i128) -> Result<C, Ordering> {
+    C::try_from(value).map_err(|_| {
+        if value < 0 {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        }
+    })
+}
+
+fn compare<T, C>(ct: &T, op: ComparisonOp, value: Result<C, Ordering>) -> FheBool
+where
+    T: FheEq<C> + FheOrd<C>,
+{
+    match value {
+        Ok(value) => match op {
+            ComparisonOp::Eq => ct.eq(value),
+            ComparisonOp::NotEq => ct.ne(value),
+            ComparisonOp::Lt => ct.lt(value),
+            ComparisonOp::LtEq => ct.le(value),
+            ComparisonOp::Gt => ct.gt(value),
+            ComparisonOp::GtEq => ct.ge(value),
+        },
+        // The row value does not fit in the literal's type, so the result is known in clear.
+        Err(ordering) => FheBool::encrypt_trivial(op.holds(ordering)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::client::{Client, TableAccess};
+    use crate::database_server::Database;
+    use crate::parameters::ParameterProfile;
+    use crate::schema::Schema;
+
+    // Two tiny tables, loaded into SQLite and for the encrypted queries, with the keys of a
+    // client. The values are small so that every result is the same with or without wrapping.
+    struct Fixture {
+        dir: PathBuf,
+        db: Database,
+        schema: Schema,
+        tables: Tables,
+        client: Client,
+        server_key: ServerKey,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Fixture {
+            let dir = std::env::temp_dir().join(format!(
+                "encrypt_sql-server-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(
+                dir.join("t.csv"),
+                "a:uint8,name:string,flag:bool\n1,ab,true\n2,cd,false\n3,ab,true\n",
+            )
+            .unwrap();
+            fs::write(dir.join("u.csv"), "a:uint8,b:int8\n4,-2\n1,5\n").unwrap();
+            let db = Database::load_from_directory(&dir).unwrap();
+            let (mut client, server_key) = Client::new(ParameterProfile::Default.config());
+            client.set_string_length(2);
+            Fixture {
+                schema: db.schema(),
+                tables: db.to_tables().unwrap(),
+                dir,
+                db,
+                client,
+                server_key,
+            }
+        }
+
+        // Runs the queries encrypted and on SQLite, and checks that both return the same rows.
+        fn check(&self, queries: &[&str], access: TableAccess) {
+            for sql in queries {
+                let (query, layout) = self
+                    .client
+                    .encrypt_query(sql, &self.schema, access)
+                    .unwrap();
+                let result = run_fhe_query(&self.server_key, &query, &self.tables).unwrap();
+                let decrypted = self.client.decrypt_result(&result, &layout);
+                let clear = self.db.run_query(sql, &layout.column_types()).unwrap();
+                let diff = clear.diff(&decrypted);
+                assert!(diff.is_empty(), "{}:\n{}", sql, diff);
+            }
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn projects_columns() {
+        Fixture::new("projection").check(
+            &["SELECT name, a FROM t", "SELECT * FROM u"],
+            TableAccess::Clear,
+        );
+    }
+
+    #[test]
+    fn hides_the_queried_table() {
+        Fixture::new("oblivious").check(&["SELECT b FROM u WHERE a = 1"], TableAccess::Oblivious);
+    }
+
+    #[test]
+    fn removes_duplicates() {
+        Fixture::new("distinct").check(&["SELECT DISTINCT name FROM t"], TableAccess::Clear);
+    }
+
+    #[test]
+    fn filters_with_lists_and_ranges() {
+        Fixture::new("ranges").check(
+            &[
+                "SELECT a FROM u WHERE a IN (1, 3)",
+                "SELECT a FROM u WHERE a NOT IN (1, 3)",
+                "SELECT a FROM u WHERE a BETWEEN 2 AND 4",
+                "SELECT a FROM u WHERE a NOT BETWEEN 2 AND 4",
+            ],
+            TableAccess::Clear,
+        );
+    }
+
+    #[test]
+    fn compares_strings() {
+        Fixture::new("strings").check(
+            &[
+                "SELECT a FROM t WHERE name = 'ab'",
+                "SELECT a FROM t WHERE name <> 'ab'",
+            ],
+            TableAccess::Clear,
+        );
+    }
+
+    #[test]
+    fn concatenates_strings() {
+        Fixture::new("concat").check(&["SELECT name || '-' || name FROM t"], TableAccess::Clear);
+    }
+
+    #[test]
+    fn uses_bool_columns_as_predicates() {
+        Fixture::new("bools").check(
+            &[
+                "SELECT a FROM t WHERE flag",
+                "SELECT a FROM t WHERE NOT flag",
+            ],
+            TableAccess::Clear,
+        );
+    }
+
+    #[test]
+    fn compares_with_signed_and_out_of_range_literals() {
+        Fixture::new("literals").check(
+            &[
+                "SELECT b FROM u WHERE b < -1",
+                "SELECT a FROM u WHERE a < 300",
+                "SELECT a FROM u WHERE a > -1",
+            ],
+            TableAccess::Clear,
+        );
+    }
+
+    #[test]
+    fn computes_arithmetic() {
+        Fixture::new("arithmetic").check(
+            &[
+                "SELECT a / 3, a % 3 FROM u",
+                "SELECT b - a FROM u",
+                "SELECT a FROM u WHERE a * 2 = 8",
+                // Division by zero is NULL, and an out-of-range divisor still divides exactly.
+                "SELECT a / 0 FROM u",
+                "SELECT a / 300 FROM u",
+            ],
+            TableAccess::Clear,
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Long-running query server over TCP. The tables are loaded once and shared by every session.
+//!
+//! A session is one connection: the client first registers its server key, then sends encrypted
+//! queries one after the other, and gets an encrypted result (or an error) back for each. Every
+//! session runs in its own thread with its own server key, so clients with different keys, or
+//! even different parameters, can query the same server at the same time.
+
+use std::io::{BufRead, BufReader, BufWriter};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Instant;
+
+use tfhe::ServerKey;
+
+use crate::parameters::ParameterProfile;
+use crate::query::{EncryptedQuery, EncryptedResult};
+use crate::server;
+use crate::tables::Tables;
+use crate::wire::{self, WireError};
+
+pub(crate) const DEFAULT_ADDRESS: &str = "127.0.0.1:7433";
+// Most sessions served at once, as each one holds a server key of up to a few hundred MiB. Further
+// connections wait in the listen backlog until a session ends.
+const MAX_SESSIONS: usize = 8;
+
+pub(crate) struct Service {
+    listener: TcpListener,
+    tables: Arc<Tables>,
+}
+
+impl Service {
+    /// Binds the server to `address`. Port 0 picks a free port, see `local_addr`.
+    pub fn bind(address: impl ToSocketAddrs, tables: Tables) -> Result<Service, WireError> {
+        Ok(Service {
+            listener: TcpListener::bind(address)?,
+            tables: Arc::new(tables),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, WireError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections until the listener fails, with one thread per session and at most
+    /// `MAX_SESSIONS` sessions at once. A failed session is logged and does not stop the server.
+    pub fn run(&self) -> Result<(), WireError> {
+        // A session takes a token before it is accepted and gives it back when it ends.
+        let (release, acquire) = mpsc::sync_channel(MAX_SESSIONS);
+        for _ in 0..MAX_SESSIONS {
+            release
+                .send(())
+                .expect("the channel holds a token per session");
+        }
+        loop {
+            acquire.recv().expect("the loop holds a sender");
+            let (stream, _) = self.listener.accept()?;
+            let tables = Arc::clone(&self.tables);
+            let release = release.clone();
+            thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map_or_else(|_| "unknown peer".to_string(), |addr| addr.to_string());
+                match serve_session(stream, &tables) {
+                    Ok(query_count) => println!("{}: {} query(ies) served", peer, query_count),
+                    Err(err) => eprintln!("{}: {}", peer, err),
+                }
+                // Only fails once the server has stopped accepting connections.
+                let _ = release.send(());
+            });
+        }
+    }
+}
+
+// Runs the queries of one session until the client closes the connection. Returns the number
+// of queries run.
+fn serve_session(stream: TcpStream, tables: &Tables) -> Result<usize, WireError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let (server_key, profile) = wire::read_server_key(&mut reader)?;
+    let mut query_count = 0;
+    // A session ends cleanly when the client closes the connection between two queries.
+    while !reader.fill_buf()?.is_empty() {
+        let query = wire::read_query(&mut reader, profile)?;
+        let start = Instant::now();
+        match server::run_fhe_query(&server_key, &query, tables) {
+            Ok(result) => {
+                println!(
+                    "Query run in {:.2?} (parameters: {})",
+                    start.elapsed(),
+                    profile
+                );
+                wire::write_result(&mut writer, &result, profile)?;
+            }
+            // The query was malformed: the client is told why and may send another one.
+            Err(err) => wire::write_error(&mut writer, &err.to_string(), profile)?,
+        }
+        query_count += 1;
+    }
+    Ok(query_count)
+}
+
+/// Client side of a session.
+pub(crate) struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    profile: ParameterProfile,
+}
+
+impl Connection {
+    /// Connects to a server and registers the server key the session's queries will run with.
+    pub fn open(
+        address: impl ToSocketAddrs,
+        server_key: &ServerKey,
+        profile: ParameterProfile,
+    ) -> Result<Connection, WireError> {
+        let stream = TcpStream::connect(address)?;
+        let mut connection = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+            profile,
+        };
+        wire::write_server_key(&mut connection.writer, server_key, profile)?;
+        Ok(connection)
+    }
+
+    /// Sends a query and waits for its result.
+    pub fn query(&mut self, query: &EncryptedQuery) -> Result<EncryptedResult, WireError> {
+        wire::write_query(&mut self.writer, query, self.profile)?;
+        wire::read_result(&mut self.reader, self.profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::client::{Client, TableAccess};
+    use crate::schema::{Row, Schema, TableSchema, Value};
+    use crate::tables::Table;
+
+    fn table(name: &str) -> TableSchema {
+        TableSchema::from_headers(Path::new(&format!("{}.csv", name)), ["id:uint8"]).unwrap()
+    }
+
+    #[test]
+    fn serves_sessions_with_their_own_keys() {
+        let schema = Schema {
+            tables: vec![table("t")],
+        };
+        let row = schema.tables[0]
+            .parse_row(Path::new("t.csv"), 2, &["7"])
+            .unwrap();
+        let mut tables = Tables::new();
+        tables.insert_table(Table::from_rows(table("t"), &[row]));
+
+        let service = Service::bind("127.0.0.1:0", tables).unwrap();
+        let address = service.local_addr().unwrap();
+        thread::spawn(move || service.run());
+
+        // Both sessions are open at once, each with the server key of its own client.
+        let profile = ParameterProfile::Default;
+        let mut sessions = Vec::new();
+        for _ in 0..2 {
+            let (client, server_key) = Client::new(profile.config());
+            let connection = Connection::open(address, &server_key, profile).unwrap();
+            sessions.push((client, connection));
+        }
+
+        // A query the server cannot run is answered with an error, and the session goes on.
+        let (client, connection) = &mut sessions[0];
+        let other_schema = Schema {
+            tables: vec![table("missing")],
+        };
+        let (query, _) = client
+            .encrypt_query("SELECT id FROM missing", &other_schema, TableAccess::Clear)
+            .unwrap();
+        assert!(matches!(
+            connection.query(&query),
+            Err(WireError::Remote(_))
+        ));
+
+        for (client, connection) in &mut sessions {
+            let (query, layout) = client
+                .encrypt_query("SELECT id FROM t", &schema, TableAccess::Clear)
+                .unwrap();
+            let result = connection.query(&query).unwrap();
+            assert_eq!(
+                client.decrypt_result(&result, &layout).rows,
+                [Row {
+                    values: vec![Value::Integer(7)],
+                }]
+            );
+        }
+    }
+}
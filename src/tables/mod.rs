@@ -0,0 +1,252 @@
+//! The server's copy of the database: one typed, columnar table per CSV file, in schema order.
+
+use std::borrow::Cow;
+
+use crate::schema::{DataType, IntegerType, Row, TableSchema, Value};
+
+#[derive(Debug, Default)]
+pub(crate) struct Tables {
+    // In the order of the schema, so a table's index is its encrypted position.
+    tables: Vec<Table>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Table {
+    schema: TableSchema,
+    // One vector per column, in schema order.
+    columns: Vec<ColumnData>,
+    row_count: usize,
+}
+
+/// The values of one column, stored with the type declared in the CSV header.
+#[derive(Debug)]
+pub(crate) enum ColumnData {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+    U64(Vec<u64>),
+    I8(Vec<i8>),
+    I16(Vec<i16>),
+    I32(Vec<i32>),
+    I64(Vec<i64>),
+    Boolean(Vec<bool>),
+    String(FixedStrings),
+}
+
+/// Strings stored back to back, each zero-padded to the longest string of the column.
+#[derive(Debug)]
+pub(crate) struct FixedStrings {
+    width: usize,
+    bytes: Vec<u8>,
+}
+
+/// A value read from a table, borrowing string bytes from their column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ValueRef<'a> {
+    Integer(i64),
+    Boolean(bool),
+    String(&'a [u8]),
+}
+
+/// Row-wise view of a table.
+#[derive(Clone, Copy)]
+pub(crate) struct TableRow<'a> {
+    table: &'a Table,
+    index: usize,
+}
+
+impl Tables {
+    pub fn new() -> Tables {
+        Tables::default()
+    }
+
+    // Adds a table, replacing any previous table with the same name.
+    pub fn insert_table(&mut self, table: Table) {
+        match self
+            .tables
+            .iter_mut()
+            .find(|t| t.schema.name == table.schema.name)
+        {
+            Some(existing) => *existing = table,
+            None => self.tables.push(table),
+        }
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|table| table.schema.name == name)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Table> {
+        self.tables.iter()
+    }
+}
+
+impl Table {
+    /// Stores rows column by column. The rows must have been parsed against `schema`.
+    pub fn from_rows(schema: TableSchema, rows: &[Row]) -> Table {
+        let columns = schema
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                ColumnData::from_values(column.data_type, rows.iter().map(|row| &row.values[index]))
+            })
+            .collect();
+        Table {
+            schema,
+            columns,
+            row_count: rows.len(),
+        }
+    }
+
+    pub fn columns(&self) -> &[ColumnData] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.row_count
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = TableRow<'_>> {
+        (0..self.row_count).map(move |index| TableRow { table: self, index })
+    }
+
+    // Size of the widest cell of the table once encoded as bytes.
+    pub fn byte_width(&self) -> usize {
+        self.columns
+            .iter()
+            .map(ColumnData::byte_width)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl<'a> TableRow<'a> {
+    pub fn value(&self, column: usize) -> Option<ValueRef<'a>> {
+        self.table
+            .columns
+            .get(column)
+            .map(|data| data.value(self.index))
+    }
+
+    pub fn bytes(&self, column: usize) -> Option<Cow<'a, [u8]>> {
+        self.table
+            .columns
+            .get(column)
+            .map(|data| data.bytes(self.index))
+    }
+
+    // Value of the column with the given name.
+    pub fn get(&self, name: &str) -> Option<ValueRef<'a>> {
+        self.value(self.table.schema.column_index(name)?)
+    }
+}
+
+impl ColumnData {
+    fn from_values<'v>(data_type: DataType, values: impl Iterator<Item = &'v Value>) -> ColumnData {
+        let integer = |value: &Value| match value {
+            Value::Integer(value) => *value,
+            other => panic!("expected an integer, found {:?}", other),
+        };
+        match data_type {
+            DataType::Integer(integer_type) => match integer_type {
+                IntegerType::Unsigned8 => {
+                    ColumnData::U8(values.map(|v| integer(v) as u8).collect())
+                }
+                IntegerType::Unsigned16 => {
+                    ColumnData::U16(values.map(|v| integer(v) as u16).collect())
+                }
+                IntegerType::Unsigned32 => {
+                    ColumnData::U32(values.map(|v| integer(v) as u32).collect())
+                }
+                IntegerType::Unsigned64 => {
+                    ColumnData::U64(values.map(|v| integer(v) as u64).collect())
+                }
+                IntegerType::Signed8 => ColumnData::I8(values.map(|v| integer(v) as i8).collect()),
+                IntegerType::Signed16 => {
+                    ColumnData::I16(values.map(|v| integer(v) as i16).collect())
+                }
+                IntegerType::Signed32 => {
+                    ColumnData::I32(values.map(|v| integer(v) as i32).collect())
+                }
+                IntegerType::Signed64 => ColumnData::I64(values.map(integer).collect()),
+            },
+            DataType::Boolean => ColumnData::Boolean(
+                values
+                    .map(|value| match value {
+                        Value::Boolean(value) => *value,
+                        other => panic!("expected a bool, found {:?}", other),
+                    })
+                    .collect(),
+            ),
+            DataType::String => {
+                let strings: Vec<&str> = values
+                    .map(|value| match value {
+                        Value::String(value) => value.as_str(),
+                        other => panic!("expected a string, found {:?}", other),
+                    })
+                    .collect();
+                ColumnData::String(FixedStrings::new(&strings))
+            }
+        }
+    }
+
+    pub fn value(&self, row: usize) -> ValueRef<'_> {
+        match self {
+            ColumnData::U8(values) => ValueRef::Integer(values[row] as i64),
+            ColumnData::U16(values) => ValueRef::Integer(values[row] as i64),
+            ColumnData::U32(values) => ValueRef::Integer(values[row] as i64),
+            ColumnData::U64(values) => ValueRef::Integer(values[row] as i64),
+            ColumnData::I8(values) => ValueRef::Integer(values[row] as i64),
+            ColumnData::I16(values) => ValueRef::Integer(values[row] as i64),
+            ColumnData::I32(values) => ValueRef::Integer(values[row] as i64),
+            ColumnData::I64(values) => ValueRef::Integer(values[row]),
+            ColumnData::Boolean(values) => ValueRef::Boolean(values[row]),
+            ColumnData::String(strings) => ValueRef::String(strings.get(row)),
+        }
+    }
+
+    /// Encodes a value as the bytes of a projected cell: integers in little-endian order at
+    /// the width of their type, bools as one byte, strings as their bytes.
+    pub fn bytes(&self, row: usize) -> Cow<'_, [u8]> {
+        let width = self.byte_width();
+        match self.value(row) {
+            ValueRef::Integer(value) => Cow::Owned(value.to_le_bytes()[..width].to_vec()),
+            ValueRef::Boolean(value) => Cow::Owned(vec![value as u8]),
+            ValueRef::String(bytes) => Cow::Borrowed(bytes),
+        }
+    }
+
+    // Number of bytes needed to encode any value of the column.
+    pub fn byte_width(&self) -> usize {
+        match self {
+            ColumnData::U8(_) | ColumnData::I8(_) | ColumnData::Boolean(_) => 1,
+            ColumnData::U16(_) | ColumnData::I16(_) => 2,
+            ColumnData::U32(_) | ColumnData::I32(_) => 4,
+            ColumnData::U64(_) | ColumnData::I64(_) => 8,
+            ColumnData::String(strings) => strings.width,
+        }
+    }
+}
+
+impl FixedStrings {
+    fn new(strings: &[&str]) -> FixedStrings {
+        let width = strings.iter().map(|s| s.len()).max().unwrap_or(0);
+        let mut bytes = Vec::with_capacity(width * strings.len());
+        for s in strings {
+            bytes.extend_from_slice(s.as_bytes());
+            bytes.resize(bytes.len() + width - s.len(), 0);
+        }
+        FixedStrings { width, bytes }
+    }
+
+    // The bytes of a string, without its padding.
+    pub fn get(&self, row: usize) -> &[u8] {
+        let padded = &self.bytes[row * self.width..(row + 1) * self.width];
+        let len = padded
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |last| last + 1);
+        &padded[..len]
+    }
+}
@@ -0,0 +1,422 @@
+//! Binary format of the encrypted query and the encrypted result, so that the client and the
+//! server can run in different processes. Each message starts with a header holding a magic
+//! number telling what it carries, the format version and the fingerprint of the parameters its
+//! ciphertexts were encrypted with; the ciphertexts follow. Messages are self-delimiting, so the
+//! same functions read and write them over a socket.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tfhe::ServerKey;
+
+use crate::parameters::{ParameterProfile, PROFILES};
+use crate::query::{EncryptedQuery, EncryptedResult};
+
+const QUERY_MAGIC: [u8; 4] = *b"ESQQ";
+const RESULT_MAGIC: [u8; 4] = *b"ESQR";
+const SERVER_KEY_MAGIC: [u8; 4] = *b"ESQS";
+// Sent by a server instead of a result when the query could not be run.
+const ERROR_MAGIC: [u8; 4] = *b"ESQE";
+const FORMAT_VERSION: u16 = 4;
+
+// Largest payloads read, so that a peer announcing a huge length cannot exhaust memory. Server
+// keys of the profiles of this build take up to about 200 MiB; results grow with the table sizes.
+const SERVER_KEY_LIMIT: u64 = 512 << 20;
+const QUERY_LIMIT: u64 = 256 << 20;
+const RESULT_LIMIT: u64 = 4 << 30;
+const HEADER_LIMIT: u64 = 64;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    magic: [u8; 4],
+    version: u16,
+    fingerprint: u64,
+}
+
+#[derive(Debug)]
+pub(crate) enum WireError {
+    Io(std::io::Error),
+    Encoding(bincode::Error),
+    // The data does not start with the expected magic number.
+    Magic { expected: &'static str },
+    Version(u16),
+    // The ciphertexts were encrypted with other parameters than the ones in use.
+    Parameters { expected: ParameterProfile },
+    // The fingerprint does not match any parameter profile of this build.
+    UnknownParameters,
+    // The server answered with an error instead of a result.
+    Remote(String),
+    // A file could not be read or written.
+    File(PathBuf, Box<WireError>),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            WireError::Io(ref err) => write!(f, "IO error: {}", err),
+            WireError::Encoding(ref err) => write!(f, "Encoding error: {}", err),
+            WireError::Magic { expected } => write!(f, "Not {}", expected),
+            WireError::Version(version) => write!(
+                f,
+                "Written in format version {}, this build reads version {}",
+                version, FORMAT_VERSION
+            ),
+            WireError::Parameters { expected } => write!(
+                f,
+                "Encrypted with other parameters than the `{}` ones in use",
+                expected
+            ),
+            WireError::UnknownParameters => {
+                write!(f, "Encrypted with parameters unknown to this build")
+            }
+            WireError::Remote(ref message) => write!(f, "Server error: {}", message),
+            WireError::File(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+        }
+    }
+}
+
+impl Error for WireError {}
+
+impl From<std::io::Error> for WireError {
+    fn from(err: std::io::Error) -> WireError {
+        WireError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for WireError {
+    fn from(err: bincode::Error) -> WireError {
+        WireError::Encoding(err)
+    }
+}
+
+pub(crate) fn write_query<W: Write>(
+    writer: W,
+    query: &EncryptedQuery,
+    profile: ParameterProfile,
+) -> Result<(), WireError> {
+    encode(writer, QUERY_MAGIC, profile, query)
+}
+
+pub(crate) fn read_query<R: Read>(
+    reader: R,
+    profile: ParameterProfile,
+) -> Result<EncryptedQuery, WireError> {
+    decode(
+        reader,
+        QUERY_MAGIC,
+        "an encrypted query",
+        profile,
+        QUERY_LIMIT,
+    )
+}
+
+pub(crate) fn write_result<W: Write>(
+    writer: W,
+    result: &EncryptedResult,
+    profile: ParameterProfile,
+) -> Result<(), WireError> {
+    encode(writer, RESULT_MAGIC, profile, result)
+}
+
+/// Reads a result, or the error a server sent in its place as `WireError::Remote`.
+pub(crate) fn read_result<R: Read>(
+    mut reader: R,
+    profile: ParameterProfile,
+) -> Result<EncryptedResult, WireError> {
+    let header: Header = read_limited(&mut reader, HEADER_LIMIT)?;
+    if header.magic == ERROR_MAGIC && header.version == FORMAT_VERSION {
+        return Err(WireError::Remote(read_limited(&mut reader, QUERY_LIMIT)?));
+    }
+    check_header(&header, RESULT_MAGIC, "an encrypted result", profile)?;
+    read_limited(&mut reader, RESULT_LIMIT)
+}
+
+pub(crate) fn write_error<W: Write>(
+    writer: W,
+    message: &str,
+    profile: ParameterProfile,
+) -> Result<(), WireError> {
+    encode(writer, ERROR_MAGIC, profile, &message)
+}
+
+/// Sends the server key a session will run its queries with.
+pub(crate) fn write_server_key<W: Write>(
+    writer: W,
+    server_key: &ServerKey,
+    profile: ParameterProfile,
+) -> Result<(), WireError> {
+    encode(writer, SERVER_KEY_MAGIC, profile, server_key)
+}
+
+/// Reads a server key generated with any profile known to this build, and returns it with that
+/// profile.
+pub(crate) fn read_server_key<R: Read>(
+    mut reader: R,
+) -> Result<(ServerKey, ParameterProfile), WireError> {
+    let header: Header = read_limited(&mut reader, HEADER_LIMIT)?;
+    let profile = PROFILES
+        .into_iter()
+        .find(|profile| profile.fingerprint() == header.fingerprint)
+        .ok_or(WireError::UnknownParameters)?;
+    check_header(&header, SERVER_KEY_MAGIC, "a server key", profile)?;
+    Ok((read_limited(&mut reader, SERVER_KEY_LIMIT)?, profile))
+}
+
+/// Writes an encrypted query to a file, e.g. `query.enc`.
+pub(crate) fn write_query_file(
+    path: &Path,
+    query: &EncryptedQuery,
+    profile: ParameterProfile,
+) -> Result<(), WireError> {
+    with_path(path, || {
+        write_query(BufWriter::new(File::create(path)?), query, profile)
+    })
+}
+
+pub(crate) fn read_query_file(
+    path: &Path,
+    profile: ParameterProfile,
+) -> Result<EncryptedQuery, WireError> {
+    with_path(path, || {
+        read_query(BufReader::new(File::open(path)?), profile)
+    })
+}
+
+/// Writes an encrypted result to a file, e.g. `result.enc`.
+pub(crate) fn write_result_file(
+    path: &Path,
+    result: &EncryptedResult,
+    profile: ParameterProfile,
+) -> Result<(), WireError> {
+    with_path(path, || {
+        write_result(BufWriter::new(File::create(path)?), result, profile)
+    })
+}
+
+pub(crate) fn read_result_file(
+    path: &Path,
+    profile: ParameterProfile,
+) -> Result<EncryptedResult, WireError> {
+    with_path(path, || {
+        read_result(BufReader::new(File::open(path)?), profile)
+    })
+}
+
+fn encode<W: Write, T: Serialize>(
+    mut writer: W,
+    magic: [u8; 4],
+    profile: ParameterProfile,
+    payload: &T,
+) -> Result<(), WireError> {
+    let header = Header {
+        magic,
+        version: FORMAT_VERSION,
+        fingerprint: profile.fingerprint(),
+    };
+    bincode::serialize_into(&mut writer, &header)?;
+    bincode::serialize_into(&mut writer, payload)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// The header is checked before any ciphertext is decoded.
+fn decode<R: Read, T: DeserializeOwned>(
+    mut reader: R,
+    magic: [u8; 4],
+    expected: &'static str,
+    profile: ParameterProfile,
+    limit: u64,
+) -> Result<T, WireError> {
+    let header: Header = read_limited(&mut reader, HEADER_LIMIT)?;
+    check_header(&header, magic, expected, profile)?;
+    read_limited(&mut reader, limit)
+}
+
+// Reads a value encoded by `bincode::serialize_into`, failing instead of allocating when it
+// announces more than `limit` bytes.
+fn read_limited<R: Read, T: DeserializeOwned>(reader: R, limit: u64) -> Result<T, WireError> {
+    Ok(bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+        .deserialize_from(reader)?)
+}
+
+fn check_header(
+    header: &Header,
+    magic: [u8; 4],
+    expected: &'static str,
+    profile: ParameterProfile,
+) -> Result<(), WireError> {
+    if header.magic != magic {
+        return Err(WireError::Magic { expected });
+    }
+    if header.version != FORMAT_VERSION {
+        return Err(WireError::Version(header.version));
+    }
+    if header.fingerprint != profile.fingerprint() {
+        return Err(WireError::Parameters { expected: profile });
+    }
+    Ok(())
+}
+
+fn with_path<T>(path: &Path, f: impl FnOnce() -> Result<T, WireError>) -> Result<T, WireError> {
+    f().map_err(|err| WireError::File(path.to_path_buf(), Box::new(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::client::{Client, TableAccess};
+    use crate::schema::{Row, Schema, TableSchema, Value};
+    use crate::server;
+    use crate::tables::{Table, Tables};
+
+    const PROFILE: ParameterProfile = ParameterProfile::Default;
+
+    fn message(magic: [u8; 4], version: u16, fingerprint: u64) -> Vec<u8> {
+        let header = Header {
+            magic,
+            version,
+            fingerprint,
+        };
+        let mut bytes = bincode::serialize(&header).unwrap();
+        bytes.extend(bincode::serialize(&7u64).unwrap());
+        bytes
+    }
+
+    fn read(bytes: &[u8]) -> Result<u64, WireError> {
+        decode(
+            bytes,
+            QUERY_MAGIC,
+            "an encrypted query",
+            PROFILE,
+            QUERY_LIMIT,
+        )
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let mut bytes = Vec::new();
+        encode(&mut bytes, QUERY_MAGIC, PROFILE, &7u64).unwrap();
+        assert_eq!(
+            bytes,
+            message(QUERY_MAGIC, FORMAT_VERSION, PROFILE.fingerprint())
+        );
+        assert_eq!(read(&bytes).unwrap(), 7);
+    }
+
+    #[test]
+    fn rejects_other_messages_versions_and_parameters() {
+        let fingerprint = PROFILE.fingerprint();
+        assert!(matches!(
+            read(&message(RESULT_MAGIC, FORMAT_VERSION, fingerprint)),
+            Err(WireError::Magic {
+                expected: "an encrypted query"
+            })
+        ));
+        assert!(matches!(
+            read(&message(QUERY_MAGIC, FORMAT_VERSION + 1, fingerprint)),
+            Err(WireError::Version(version)) if version == FORMAT_VERSION + 1
+        ));
+        let other = ParameterProfile::SmallMessage.fingerprint();
+        assert!(matches!(
+            read(&message(QUERY_MAGIC, FORMAT_VERSION, other)),
+            Err(WireError::Parameters { expected: PROFILE })
+        ));
+    }
+
+    #[test]
+    fn rejects_server_keys_of_unknown_parameters() {
+        let unknown = PROFILES
+            .iter()
+            .map(|profile| profile.fingerprint())
+            .fold(0, u64::wrapping_add);
+        assert!(matches!(
+            read_server_key(&message(SERVER_KEY_MAGIC, FORMAT_VERSION, unknown)[..]),
+            Err(WireError::UnknownParameters)
+        ));
+    }
+
+    #[test]
+    fn reads_a_server_error_in_place_of_a_result() {
+        let mut bytes = Vec::new();
+        write_error(&mut bytes, "unknown column `x`", PROFILE).unwrap();
+        assert!(matches!(
+            read_result(&bytes[..], PROFILE),
+            Err(WireError::Remote(message)) if message == "unknown column `x`"
+        ));
+    }
+
+    #[test]
+    fn rejects_payloads_beyond_the_limit() {
+        // A string announcing far more bytes than the message may hold.
+        let mut bytes = bincode::serialize(&Header {
+            magic: QUERY_MAGIC,
+            version: FORMAT_VERSION,
+            fingerprint: PROFILE.fingerprint(),
+        })
+        .unwrap();
+        bytes.extend(bincode::serialize(&u64::MAX).unwrap());
+        assert!(matches!(
+            decode::<_, String>(&bytes[..], QUERY_MAGIC, "an encrypted query", PROFILE, 1024),
+            Err(WireError::Encoding(_))
+        ));
+    }
+
+    #[test]
+    fn round_trips_queries_and_results_through_files() {
+        let dir = std::env::temp_dir().join(format!("encrypt_sql-wire-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (query_path, result_path) = (dir.join("query.enc"), dir.join("result.enc"));
+
+        let table = TableSchema::from_headers(Path::new("t.csv"), ["id:uint8"]).unwrap();
+        let row = table.parse_row(Path::new("t.csv"), 2, &["7"]).unwrap();
+        let mut tables = Tables::new();
+        tables.insert_table(Table::from_rows(table.clone(), &[row]));
+        let schema = Schema {
+            tables: vec![table],
+        };
+
+        let (client, server_key) = Client::new(PROFILE.config());
+        let (query, layout) = client
+            .encrypt_query("SELECT id FROM t", &schema, TableAccess::Clear)
+            .unwrap();
+        write_query_file(&query_path, &query, PROFILE).unwrap();
+        let query = read_query_file(&query_path, PROFILE).unwrap();
+        let result = server::run_fhe_query(&server_key, &query, &tables).unwrap();
+        write_result_file(&result_path, &result, PROFILE).unwrap();
+        let result = read_result_file(&result_path, PROFILE).unwrap();
+        assert_eq!(
+            client.decrypt_result(&result, &layout).rows,
+            [Row {
+                values: vec![Value::Integer(7)],
+            }]
+        );
+
+        // Files written under one profile are rejected under another.
+        let other = ParameterProfile::SmallMessage;
+        for err in [
+            read_query_file(&query_path, other).err(),
+            read_result_file(&result_path, other).err(),
+        ] {
+            assert!(matches!(
+                err,
+                Some(WireError::File(_, err)) if matches!(
+                    *err,
+                    WireError::Parameters { expected: ParameterProfile::SmallMessage }
+                )
+            ));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}